@@ -0,0 +1,183 @@
+//Benchmarks for each stage of drawing a frame from transforming the mesh to filling triangles, whole
+//frames of every map in Maps and the cost of each kind of anti-aliasing.
+//Run with cargo bench --bench pipeline, criterion keeps the last run to compare against
+
+#[macro_use] extern crate criterion;
+extern crate rasterizer;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use criterion::{Criterion, BenchmarkId, BatchSize, Throughput, black_box};
+
+use rasterizer::pointf::{Point2f, Point3f};
+use rasterizer::triangle::{Triangle2D, Triangle3D, fix_triangle};
+use rasterizer::color::{Color, BlendMode, Rgba};
+use rasterizer::vertex::Attributes;
+use rasterizer::framebuffer::Framebuffer;
+use rasterizer::raster::*;
+use rasterizer::map::Map;
+use rasterizer::palette::Palette;
+use rasterizer::scene::Scene;
+use rasterizer::antialias::Antialias;
+
+//Edge lengths in pixels of the triangles the fill routines are timed with
+const FILL_SIZES: [usize; 4] = [8, 32, 128, 512];
+
+//Where the viewer starts
+fn camera() -> (Point3f, Point3f) {
+	(Point3f::new(20.0, 2.5, 20.0), Point3f::new(0.0, 180.0, 0.0))
+}
+
+//A right triangle with its corner at the top left of a size x size framebuffer, covering half of it
+fn screen_triangle(size: usize) -> ScreenTriangle<Attributes> {
+	let s = size as f32;
+	let vertex = |x: f32, y: f32, color: Color| ScreenVertex::new(Point3f::new(x, y, -1.0), Attributes::new(color));
+
+	[vertex(0.0, 0.0, Color::new(255, 0, 0)),
+	 vertex(0.0, s, Color::new(0, 255, 0)),
+	 vertex(s, 0.0, Color::new(0, 0, 255))]
+}
+
+//The mesh path Scene::render() takes every frame, transforming and projecting the first map's mesh
+//and clipping triangles against the near plane
+fn bench_mesh(c: &mut Criterion) {
+	let (cam_pos, cam_rot) = camera();
+	let mut mesh = Map::load(&maps()[0]).unwrap().mesh();
+
+	//transform() skips the work when the camera hasn't moved, so it's turned a little every other time
+	let turned = cam_rot + Point3f::new(0.0, 1.0, 0.0);
+	let mut turn = false;
+	let mut group = c.benchmark_group("mesh");
+	group.throughput(Throughput::Elements(mesh.vertex_count() as u64));
+	group.bench_function("transform", |b| {
+		b.iter(|| {
+			turn = !turn;
+			mesh.transform(black_box(cam_pos), if turn { turned } else { cam_rot });
+		})
+	});
+
+	mesh.transform(cam_pos, cam_rot);
+	group.throughput(Throughput::Elements(mesh.triangle_count() as u64));
+	group.bench_function("to_screen_space", |b| {
+		b.iter(|| {
+			for tri in 0..mesh.triangle_count() {
+				black_box(mesh.to_screen_space(black_box(tri)));
+			}
+		})
+	});
+	group.finish();
+
+	//Camera view triangles with one and two corners behind the near plane
+	let vertex = |x: f32, y: f32, z: f32| (Point3f::new(x, y, z), Attributes::new(Color::new(255, 255, 255)));
+	let one_behind = [vertex(-1.0, -1.0, -4.0), vertex(1.0, -1.0, 2.0), vertex(0.0, 1.0, -4.0)];
+	let two_behind = [vertex(-1.0, -1.0, 2.0), vertex(1.0, -1.0, 2.0), vertex(0.0, 1.0, -4.0)];
+
+	c.bench_function("clip_triangle/one_behind", |b| b.iter(|| clip_triangle(black_box(one_behind))));
+	c.bench_function("clip_triangle/two_behind", |b| b.iter(|| clip_triangle(black_box(two_behind))));
+}
+
+//The per-triangle path rendering used before the mesh, kept to compare against
+fn bench_transforms(c: &mut Criterion) {
+	let (cam_pos, cam_rot) = camera();
+	let point = Point3f::new(12.0, 1.0, 8.0);
+	let tri = Triangle3D::new(Point3f::new(12.0, 0.0, 8.0), Point3f::new(12.0, 4.0, 8.0), Point3f::new(16.0, 0.0, 8.0));
+
+	c.bench_function("to_camera_view", |b| {
+		b.iter(|| black_box(point).to_camera_view(black_box(cam_pos), black_box(cam_rot)))
+	});
+
+	c.bench_function("Triangle3D::to_screen_space", |b| {
+		b.iter(|| black_box(&tri).to_screen_space(black_box(cam_pos), black_box(cam_rot)))
+	});
+
+	let tri = Triangle2D::new(Point2f::new(100.0, 50.0), Point2f::new(40.0, 300.0), Point2f::new(420.0, 180.0));
+	c.bench_function("fix_triangle", |b| {
+		b.iter(|| fix_triangle(black_box(&tri)))
+	});
+}
+
+fn bench_fills(c: &mut Criterion) {
+	let mut group = c.benchmark_group("fill");
+
+	for &size in FILL_SIZES.iter() {
+		let tri = screen_triangle(size);
+		group.throughput(Throughput::Elements((size * size / 2) as u64));
+
+		//A fresh framebuffer each time so the depth test passes, making it isn't timed
+		group.bench_with_input(BenchmarkId::new("draw_triangle", size), &tri, |b, tri| {
+			b.iter_batched_ref(|| Framebuffer::new(size, size),
+							   |fb| draw_triangle(fb, tri, |fragment| fragment.attributes().color()),
+							   BatchSize::LargeInput)
+		});
+
+		//Depth isn't written so the same framebuffer can be drawn over and over
+		let mut fb = Framebuffer::new(size, size);
+		group.bench_with_input(BenchmarkId::new("draw_triangle_blended", size), &tri, |b, tri| {
+			b.iter(|| draw_triangle_blended(&mut fb, tri, BlendMode::Alpha, |fragment| Rgba::premultiplied(fragment.attributes().color(), 0.5)))
+		});
+
+		let mut indices = vec![0u8; size * size];
+		group.bench_with_input(BenchmarkId::new("draw_triangle_indexed", size), &tri, |b, tri| {
+			b.iter(|| draw_triangle_indexed(&mut fb, tri, &mut indices, false, |fragment| Some(fragment.x() as u8)))
+		});
+	}
+
+	group.finish();
+}
+
+fn maps() -> Vec<PathBuf> {
+	let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("Maps");
+	let mut maps: Vec<PathBuf> = fs::read_dir(&dir).unwrap()
+		.filter_map(|entry| entry.ok().map(|e| e.path()))
+		.filter(|path| path.extension().map_or(false, |e| e == "nmf"))
+		.collect();
+	maps.sort();
+	maps
+}
+
+fn bench_frames(c: &mut Criterion) {
+	let mut group = c.benchmark_group("frame");
+	group.sample_size(10);
+
+	let (cam_pos, cam_rot) = camera();
+	for path in maps() {
+		let map = Map::load(&path).unwrap();
+		let mut scene = Scene::new(map, Palette::rgb332(), 1920, 1080);
+
+		let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+		group.bench_function(name, |b| {
+			b.iter(|| scene.render(black_box(cam_pos), black_box(cam_rot)))
+		});
+	}
+
+	group.finish();
+}
+
+//The first map's frame with each kind of anti-aliasing, to weigh their cost against how they look
+fn bench_antialias(c: &mut Criterion) {
+	let mut group = c.benchmark_group("antialias");
+	group.sample_size(10);
+
+	let (cam_pos, cam_rot) = camera();
+	let map = Map::load(&maps()[0]).unwrap();
+	let mut scene = Scene::new(map, Palette::rgb332(), 1920, 1080);
+
+	let mut antialias = Antialias::Off;
+	loop {
+		scene.set_antialias(antialias);
+		group.bench_function(format!("{:?}", antialias), |b| {
+			b.iter(|| scene.render(black_box(cam_pos), black_box(cam_rot)))
+		});
+
+		antialias = antialias.next();
+		if antialias == Antialias::Off {
+			break;
+		}
+	}
+
+	group.finish();
+}
+
+criterion_group!(benches, bench_mesh, bench_transforms, bench_fills, bench_frames, bench_antialias);
+criterion_main!(benches);
@@ -0,0 +1,84 @@
+//Times coverage testing, color stores and span filling with the SIMD paths against their scalar fallbacks.
+//Run with cargo bench --bench span
+
+#[macro_use] extern crate criterion;
+extern crate rasterizer;
+
+use criterion::{Criterion, Throughput, black_box};
+
+use rasterizer::simd::*;
+use rasterizer::pointf::Point2f;
+use rasterizer::color::LinearColor;
+
+const WIDTH: usize = 1920;
+const HEIGHT: usize = 1080;
+
+//Walks every span of a triangle covering half the screen, like rasterize() does
+fn walk_triangle<F>(depth: &[f32], coverage: &mut Coverage, cover: F) -> u32
+	where F: Fn(&SpanEdges, &[f32], &mut Coverage)
+{
+	//Corners (0, 0), (WIDTH, 0) and (0, HEIGHT), so the weights are easy to write down
+	let (w, h) = (WIDTH as f32, HEIGHT as f32);
+	let corners = [Point2f::new(0.0, 0.0), Point2f::new(w, 0.0), Point2f::new(0.0, h)];
+	let area = w * h;
+	let mut edges = SpanEdges::new([Edge::new(corners[1], corners[2], area), Edge::new(corners[2], corners[0], area), Edge::new(corners[0], corners[1], area)],
+								   [-1.0 / w, 1.0 / w, 0.0], [1.0, 0.5, 0.25]);
+	let mut covered = 0;
+
+	for y in 0..HEIGHT {
+		let py = y as f32 + 0.5;
+		edges.start_row(0.5, py);
+		edges.weights = [1.0 - 0.5 / w - py / h, 0.5 / w, py / h];
+
+		let row = &depth[(y * WIDTH)..((y + 1) * WIDTH)];
+		for span in row.chunks(SPAN) {
+			cover(&edges, span, coverage);
+			covered += coverage.mask.count_ones();
+			edges.advance(SPAN);
+		}
+	}
+
+	covered
+}
+
+fn bench_coverage(c: &mut Criterion) {
+	let depth = vec![0.0; WIDTH * HEIGHT];
+	let mut coverage = Coverage::new();
+
+	let mut group = c.benchmark_group("coverage");
+	group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+	group.bench_function("scalar", |b| b.iter(|| walk_triangle(black_box(&depth), &mut coverage, cover_span_scalar)));
+	group.bench_function("simd", |b| b.iter(|| walk_triangle(black_box(&depth), &mut coverage, cover_span)));
+	group.finish();
+}
+
+fn bench_fill(c: &mut Criterion) {
+	let mut depth = vec![0.0; WIDTH * HEIGHT];
+
+	let mut group = c.benchmark_group("depth_fill");
+	group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+	group.bench_function("scalar", |b| b.iter(|| fill_scalar(black_box(&mut depth), 1.0)));
+	group.bench_function("simd", |b| b.iter(|| fill(black_box(&mut depth), 1.0)));
+	group.finish();
+}
+
+//Stores a screen's worth of fully covered spans
+fn bench_store(c: &mut Criterion) {
+	let mut color = vec![LinearColor::black(); WIDTH * HEIGHT];
+	let src = [LinearColor::new(0.25, 0.5, 1.0); SPAN];
+	let store = |color: &mut [LinearColor], store_span: fn(&mut [LinearColor], &[LinearColor], u64)| {
+		for span in color.chunks_mut(SPAN) {
+			let count = span.len();
+			store_span(span, &src[..count], !0);
+		}
+	};
+
+	let mut group = c.benchmark_group("color_store");
+	group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+	group.bench_function("scalar", |b| b.iter(|| store(black_box(&mut color), store_colors_scalar)));
+	group.bench_function("simd", |b| b.iter(|| store(black_box(&mut color), store_colors)));
+	group.finish();
+}
+
+criterion_group!(benches, bench_coverage, bench_store, bench_fill);
+criterion_main!(benches);
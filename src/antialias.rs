@@ -0,0 +1,206 @@
+use framebuffer::Framebuffer;
+
+//Most samples a pixel can have, one bit each in a sample mask
+pub const MAX_SAMPLES: usize = 8;
+
+//Where samples sit in a pixel, as offsets from its top left corner. These are the standard Direct3D
+//patterns, rotated so no two samples share a row or column and near horizontal and vertical edges
+//still get as many coverage steps as there are samples
+const PATTERN_1: [(f32, f32); 1] = [(0.5, 0.5)];
+const PATTERN_2: [(f32, f32); 2] = [(0.75, 0.75), (0.25, 0.25)];
+const PATTERN_4: [(f32, f32); 4] = [(0.375, 0.125), (0.875, 0.375), (0.125, 0.625), (0.625, 0.875)];
+const PATTERN_8: [(f32, f32); 8] = [
+	(0.5625, 0.3125), (0.4375, 0.6875), (0.8125, 0.5625), (0.3125, 0.1875),
+	(0.1875, 0.8125), (0.0625, 0.4375), (0.6875, 0.9375), (0.9375, 0.0625),
+];
+
+//Contrast below which FXAA leaves a pixel alone, as a share of the brightest luma around it and as
+//an absolute floor so dark areas aren't smoothed for their noise
+const FXAA_EDGE_THRESHOLD: f32 = 0.125;
+const FXAA_EDGE_THRESHOLD_MIN: f32 = 0.0312;
+
+//How much FXAA softens pixels that stand out from all their neighbours, like single pixel details
+const FXAA_SUBPIXEL: f32 = 0.75;
+
+//Furthest FXAA looks along an edge for its ends, in pixels each way
+const FXAA_SEARCH_STEPS: usize = 12;
+
+/*
+How triangle edges are smoothed. MSAA tests coverage and depth at several points in every pixel and
+averages them, FXAA is a cheaper filter over the finished frame that blurs across edges it finds
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Antialias {
+	Off,
+	Msaa2x,
+	Msaa4x,
+	Msaa8x,
+	Fxaa,
+}
+
+impl Antialias {
+	pub fn next(&self) -> Antialias {
+		match *self {
+			Antialias::Off => Antialias::Msaa2x,
+			Antialias::Msaa2x => Antialias::Msaa4x,
+			Antialias::Msaa4x => Antialias::Msaa8x,
+			Antialias::Msaa8x => Antialias::Fxaa,
+			Antialias::Fxaa => Antialias::Off,
+		}
+	}
+
+	//Samples per pixel the framebuffer needs
+	pub fn samples(&self) -> usize {
+		match *self {
+			Antialias::Msaa2x => 2,
+			Antialias::Msaa4x => 4,
+			Antialias::Msaa8x => 8,
+			Antialias::Off | Antialias::Fxaa => 1,
+		}
+	}
+}
+
+//Sample positions for a pixel with this many samples, anything other than 2, 4 or 8 gets the center
+pub fn sample_pattern(samples: usize) -> &'static [(f32, f32)] {
+	match samples {
+		2 => &PATTERN_2,
+		4 => &PATTERN_4,
+		8 => &PATTERN_8,
+		_ => &PATTERN_1,
+	}
+}
+
+/*
+Fast approximate anti-aliasing over the resolved pixels. Finds edges from the contrast in luma,
+follows each one to its ends to work out how far along the stair step a pixel is, and blends it
+with the neighbour across the edge by that much. Keeps its buffers between frames
+*/
+pub struct Fxaa {
+	luma: Vec<f32>,
+	source: Vec<u8>,
+}
+
+impl Fxaa {
+	pub fn new() -> Fxaa {
+		Fxaa {
+			luma: Vec::new(),
+			source: Vec::new(),
+		}
+	}
+
+	pub fn apply(&mut self, framebuffer: &mut Framebuffer) {
+		let (width, height, pitch) = (framebuffer.width(), framebuffer.height(), framebuffer.pitch());
+		if width < 3 || height < 3 {
+			return;
+		}
+
+		let pixels = framebuffer.pixels_mut();
+		self.source.clear();
+		self.source.extend_from_slice(pixels);
+
+		//Perceived brightness of the encoded pixels, green counts the most
+		self.luma.clear();
+		self.luma.extend(self.source.chunks(3).map(|p| (p[0] as f32 * 0.299 + p[1] as f32 * 0.587 + p[2] as f32 * 0.114) / 255.0));
+
+		let (luma, source) = (&self.luma, &self.source);
+		let at = |x: usize, y: usize| luma[x + y * width];
+
+		for y in 1..(height - 1) {
+			for x in 1..(width - 1) {
+				let middle = at(x, y);
+				let (north, south, west, east) = (at(x, y - 1), at(x, y + 1), at(x - 1, y), at(x + 1, y));
+
+				let brightest = middle.max(north).max(south).max(west).max(east);
+				let range = brightest - middle.min(north).min(south).min(west).min(east);
+				if range < FXAA_EDGE_THRESHOLD_MIN.max(brightest * FXAA_EDGE_THRESHOLD) {
+					continue;
+				}
+
+				let (north_west, north_east) = (at(x - 1, y - 1), at(x + 1, y - 1));
+				let (south_west, south_east) = (at(x - 1, y + 1), at(x + 1, y + 1));
+
+				//Pixels that differ from the average of all their neighbours get blended the most
+				let average = (2.0 * (north + south + west + east) + north_west + north_east + south_west + south_east) / 12.0;
+				let subpixel = ((average - middle).abs() / range).min(1.0);
+				let subpixel = subpixel * subpixel * (3.0 - 2.0 * subpixel);
+				let subpixel = subpixel * subpixel * FXAA_SUBPIXEL;
+
+				//An edge running left to right changes the most going up and down
+				let horizontal = (north + south - 2.0 * middle).abs() * 2.0
+					+ (north_west + south_west - 2.0 * west).abs()
+					+ (north_east + south_east - 2.0 * east).abs();
+				let vertical = (west + east - 2.0 * middle).abs() * 2.0
+					+ (north_west + north_east - 2.0 * north).abs()
+					+ (south_west + south_east - 2.0 * south).abs();
+				let horizontal = horizontal >= vertical;
+
+				//Which side of the pixel the edge is on, towards the neighbour that differs most
+				let (before, after) = if horizontal { (north, south) } else { (west, east) };
+				let toward_after = (after - middle).abs() >= (before - middle).abs();
+				let across = if toward_after { after } else { before };
+				let edge_luma = (middle + across) * 0.5;
+				let gradient = (across - middle).abs() * 0.25;
+
+				//Walk both ways along the edge until the luma pair straddling it stops matching, and
+				//note whether it ends darker or lighter than the edge
+				let along = |step: isize| -> (usize, bool) {
+					let (mut distance, mut difference) = (0, 0.0);
+					for i in 1..(FXAA_SEARCH_STEPS + 1) {
+						let (sx, sy) = if horizontal {
+							(x as isize + step * i as isize, y as isize)
+						} else {
+							(x as isize, y as isize + step * i as isize)
+						};
+						if sx < 0 || sy < 0 || sx >= width as isize || sy >= height as isize {
+							break;
+						}
+
+						let (ox, oy) = match (horizontal, toward_after) {
+							(true, true) => (sx, sy + 1),
+							(true, false) => (sx, sy - 1),
+							(false, true) => (sx + 1, sy),
+							(false, false) => (sx - 1, sy),
+						};
+						difference = (at(sx as usize, sy as usize) + at(ox as usize, oy as usize)) * 0.5 - edge_luma;
+						if difference.abs() >= gradient {
+							break;
+						}
+						distance = i;
+					}
+					(distance, difference < 0.0)
+				};
+				let (negative, negative_below) = along(-1);
+				let (positive, positive_below) = along(1);
+
+				//Only the end the pixel is closer to matters, and only if the edge bends away from
+				//this pixel there. Pixels in the middle of a long step hardly move
+				let middle_below = middle - edge_luma < 0.0;
+				let (distance, end_below) = if negative < positive { (negative, negative_below) } else { (positive, positive_below) };
+				let span = (negative + positive + 1) as f32;
+				let edge_blend = if end_below != middle_below { 0.5 - (distance as f32 + 0.5) / span } else { 0.0 };
+				let blend = edge_blend.max(subpixel);
+				if blend <= 0.0 {
+					continue;
+				}
+
+				let (nx, ny) = match (horizontal, toward_after) {
+					(true, true) => (x, y + 1),
+					(true, false) => (x, y - 1),
+					(false, true) => (x + 1, y),
+					(false, false) => (x - 1, y),
+				};
+				let (offset, neighbour) = (x * 3 + y * pitch, nx * 3 + ny * pitch);
+				for c in 0..3 {
+					let mixed = source[offset + c] as f32 + (source[neighbour + c] as f32 - source[offset + c] as f32) * blend;
+					pixels[offset + c] = (mixed + 0.5) as u8;
+				}
+			}
+		}
+	}
+}
+
+impl Default for Fxaa {
+	fn default() -> Fxaa {
+		Fxaa::new()
+	}
+}
@@ -1,4 +1,10 @@
-#[derive(Copy, Clone)]
+use std::ops::{Add, Mul};
+
+//Entries in the table used to encode linear light back to sRGB, enough that neighbouring
+//entries never skip an 8-bit step
+const ENCODE_TABLE_SIZE: usize = 4096;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Color {
     r: u8,
     g: u8,
@@ -14,6 +20,24 @@ impl Color {
         }
     }
 
+    //Multiplies each channel, treating 255 as 1.0
+    pub fn modulate(&self, rhs: Color) -> Color {
+        Color {
+            r: ((self.r as u16 * rhs.r as u16) / 255) as u8,
+            g: ((self.g as u16 * rhs.g as u16) / 255) as u8,
+            b: ((self.b as u16 * rhs.b as u16) / 255) as u8,
+        }
+    }
+
+    //Blends towards rhs, t of 0 is self and 1 is rhs
+    pub fn lerp(&self, rhs: Color, t: f32) -> Color {
+        Color {
+            r: (self.r as f32 + (rhs.r as f32 - self.r as f32) * t + 0.5) as u8,
+            g: (self.g as f32 + (rhs.g as f32 - self.g as f32) * t + 0.5) as u8,
+            b: (self.b as f32 + (rhs.b as f32 - self.b as f32) * t + 0.5) as u8,
+        }
+    }
+
     pub fn r(&self) -> u8 {
         self.r
     }
@@ -23,4 +47,334 @@ impl Color {
     pub fn b(&self) -> u8 {
         self.b
     }
-}
\ No newline at end of file
+}
+
+//Decodes an sRGB channel into linear light between 0 and 1
+pub fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+//Encodes linear light into an sRGB channel, values outside 0 to 1 are clamped
+pub fn linear_to_srgb(c: f32) -> u8 {
+    let c = c.max(0.0).min(1.0);
+    let encoded = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+
+    (encoded * 255.0 + 0.5) as u8
+}
+
+//All 256 decoded channel values, cheaper than calling srgb_to_linear per texel
+pub fn srgb_decode_table() -> Vec<f32> {
+    (0..256).map(|c| srgb_to_linear(c as u8)).collect()
+}
+
+/*
+Lookup table for linear_to_srgb, the framebuffer encodes every pixel of every frame
+*/
+pub struct SrgbEncoder {
+    table: Vec<u8>,
+}
+
+impl SrgbEncoder {
+    pub fn new() -> SrgbEncoder {
+        SrgbEncoder {
+            table: (0..ENCODE_TABLE_SIZE).map(|i| linear_to_srgb(i as f32 / (ENCODE_TABLE_SIZE - 1) as f32)).collect(),
+        }
+    }
+
+    pub fn encode(&self, c: f32) -> u8 {
+        let c = c.max(0.0).min(1.0);
+        self.table[(c * (ENCODE_TABLE_SIZE - 1) as f32 + 0.5) as usize]
+    }
+
+    pub fn encode_color(&self, color: LinearColor) -> Color {
+        Color::new(self.encode(color.r), self.encode(color.g), self.encode(color.b))
+    }
+}
+
+/*
+A color in linear light, 1.0 is the brightest a display can show but shading is free to go past it
+until the tone mapper brings it back down. Laid out as three f32s so spans of them can be copied
+with SIMD
+*/
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(C)]
+pub struct LinearColor {
+    r: f32,
+    g: f32,
+    b: f32,
+}
+
+impl LinearColor {
+    pub fn new(r: f32, g: f32, b: f32) -> LinearColor {
+        LinearColor {
+            r: r,
+            g: g,
+            b: b,
+        }
+    }
+
+    pub fn black() -> LinearColor {
+        LinearColor::new(0.0, 0.0, 0.0)
+    }
+
+    pub fn from_srgb(color: Color) -> LinearColor {
+        LinearColor::new(srgb_to_linear(color.r), srgb_to_linear(color.g), srgb_to_linear(color.b))
+    }
+
+    //Clamps to the displayable range, use a ToneMapper first to keep highlights
+    pub fn to_srgb(&self) -> Color {
+        Color::new(linear_to_srgb(self.r), linear_to_srgb(self.g), linear_to_srgb(self.b))
+    }
+
+    //Blends towards rhs, t of 0 is self and 1 is rhs
+    pub fn lerp(&self, rhs: LinearColor, t: f32) -> LinearColor {
+        LinearColor {
+            r: self.r + (rhs.r - self.r) * t,
+            g: self.g + (rhs.g - self.g) * t,
+            b: self.b + (rhs.b - self.b) * t,
+        }
+    }
+
+    //Perceived brightness, Rec. 709 weights
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn r(&self) -> f32 {
+        self.r
+    }
+    pub fn g(&self) -> f32 {
+        self.g
+    }
+    pub fn b(&self) -> f32 {
+        self.b
+    }
+}
+
+impl Add for LinearColor {
+    type Output = LinearColor;
+
+    fn add(self, rhs: LinearColor) -> LinearColor {
+        LinearColor::new(self.r + rhs.r, self.g + rhs.g, self.b + rhs.b)
+    }
+}
+
+//Multiplies each channel, for filtering light through a surface color
+impl Mul for LinearColor {
+    type Output = LinearColor;
+
+    fn mul(self, rhs: LinearColor) -> LinearColor {
+        LinearColor::new(self.r * rhs.r, self.g * rhs.g, self.b * rhs.b)
+    }
+}
+
+impl Mul<f32> for LinearColor {
+    type Output = LinearColor;
+
+    fn mul(self, rhs: f32) -> LinearColor {
+        LinearColor::new(self.r * rhs, self.g * rhs, self.b * rhs)
+    }
+}
+
+/*
+Brings high dynamic range colors into 0 to 1 for display
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ToneMapper {
+    //Cuts off anything brighter than 1, bright areas lose their detail
+    Clamp,
+    //x / (1 + x), never quite reaches white
+    Reinhard,
+    //Narkowicz's fit of the ACES filmic curve, keeps more contrast than Reinhard
+    Aces,
+}
+
+impl ToneMapper {
+    //Cycles through the tone mappers, for switching at runtime
+    pub fn next(&self) -> ToneMapper {
+        match *self {
+            ToneMapper::Clamp => ToneMapper::Reinhard,
+            ToneMapper::Reinhard => ToneMapper::Aces,
+            ToneMapper::Aces => ToneMapper::Clamp,
+        }
+    }
+
+    pub fn map(&self, color: LinearColor) -> LinearColor {
+        let curve = |x: f32| -> f32 {
+            let x = x.max(0.0);
+            match *self {
+                ToneMapper::Clamp => x.min(1.0),
+                ToneMapper::Reinhard => x / (1.0 + x),
+                ToneMapper::Aces => ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).min(1.0),
+            }
+        };
+
+        LinearColor::new(curve(color.r), curve(color.g), curve(color.b))
+    }
+}
+
+/*
+How a translucent color is combined with what is already in the framebuffer
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum BlendMode {
+    //Covers the destination by alpha
+    Alpha,
+    //Adds light, good for glows
+    Additive,
+    //Darkens by the source color, good for tinted glass
+    Multiply,
+}
+
+impl BlendMode {
+    //Combines a premultiplied source with an opaque destination, both in linear light
+    pub fn blend(&self, src: Rgba, dst: LinearColor) -> LinearColor {
+        let dst = Rgba::from(dst);
+        let inv_alpha = 1.0 - src.a;
+
+        match *self {
+            BlendMode::Alpha => (src + dst * inv_alpha).rgb(),
+            BlendMode::Additive => (dst + src).rgb(),
+            //Where the source is transparent the destination is multiplied by white instead
+            BlendMode::Multiply => (dst * (src + Rgba::new(inv_alpha, inv_alpha, inv_alpha, inv_alpha))).rgb(),
+        }
+    }
+}
+
+/*
+A linear light color with coverage, alpha of 1 is opaque. Blending expects the channels already
+scaled by alpha, premultiplied() builds one from a straight color
+*/
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Rgba {
+    r: f32,
+    g: f32,
+    b: f32,
+    a: f32,
+}
+
+impl Rgba {
+    //Takes the channels as they are, so they should already be premultiplied
+    pub fn new(r: f32, g: f32, b: f32, a: f32) -> Rgba {
+        Rgba {
+            r: r,
+            g: g,
+            b: b,
+            a: a,
+        }
+    }
+
+    //Scales a straight color by alpha
+    pub fn premultiplied(color: LinearColor, a: f32) -> Rgba {
+        Rgba::new(color.r * a, color.g * a, color.b * a, a)
+    }
+
+    //Blends towards rhs, t of 0 is self and 1 is rhs
+    pub fn lerp(&self, rhs: Rgba, t: f32) -> Rgba {
+        Rgba {
+            r: self.r + (rhs.r - self.r) * t,
+            g: self.g + (rhs.g - self.g) * t,
+            b: self.b + (rhs.b - self.b) * t,
+            a: self.a + (rhs.a - self.a) * t,
+        }
+    }
+
+    //Drops alpha
+    pub fn rgb(&self) -> LinearColor {
+        LinearColor::new(self.r, self.g, self.b)
+    }
+
+    pub fn r(&self) -> f32 {
+        self.r
+    }
+    pub fn g(&self) -> f32 {
+        self.g
+    }
+    pub fn b(&self) -> f32 {
+        self.b
+    }
+    pub fn a(&self) -> f32 {
+        self.a
+    }
+}
+
+//Opaque, decoded into linear light
+impl From<Color> for Rgba {
+    fn from(color: Color) -> Rgba {
+        Rgba::from(LinearColor::from_srgb(color))
+    }
+}
+
+impl From<LinearColor> for Rgba {
+    fn from(color: LinearColor) -> Rgba {
+        Rgba::new(color.r, color.g, color.b, 1.0)
+    }
+}
+
+impl Add for Rgba {
+    type Output = Rgba;
+
+    fn add(self, rhs: Rgba) -> Rgba {
+        Rgba::new(self.r + rhs.r, self.g + rhs.g, self.b + rhs.b, self.a + rhs.a)
+    }
+}
+
+//Multiplies each channel, alpha included
+impl Mul for Rgba {
+    type Output = Rgba;
+
+    fn mul(self, rhs: Rgba) -> Rgba {
+        Rgba::new(self.r * rhs.r, self.g * rhs.g, self.b * rhs.b, self.a * rhs.a)
+    }
+}
+
+impl Mul<f32> for Rgba {
+    type Output = Rgba;
+
+    fn mul(self, rhs: f32) -> Rgba {
+        Rgba::new(self.r * rhs, self.g * rhs, self.b * rhs, self.a * rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgba_from_color_is_opaque_linear_light() {
+        let rgba = Rgba::from(Color::new(255, 128, 0));
+        assert_eq!(rgba.rgb(), LinearColor::from_srgb(Color::new(255, 128, 0)));
+        assert_eq!(rgba.a(), 1.0);
+    }
+
+    #[test]
+    fn premultiplied_scales_the_channels() {
+        assert_eq!(Rgba::premultiplied(LinearColor::new(1.0, 0.5, 0.25), 0.5), Rgba::new(0.5, 0.25, 0.125, 0.5));
+        assert_eq!(Rgba::new(0.0, 0.0, 0.0, 0.0).lerp(Rgba::new(1.0, 0.5, 0.25, 1.0), 0.5), Rgba::new(0.5, 0.25, 0.125, 0.5));
+    }
+
+    #[test]
+    fn blend_modes() {
+        let src = Rgba::premultiplied(LinearColor::new(1.0, 0.5, 0.0), 0.5);
+        let dst = LinearColor::new(0.0, 0.5, 1.0);
+
+        assert_eq!(BlendMode::Alpha.blend(src, dst), LinearColor::new(0.5, 0.5, 0.5));
+        assert_eq!(BlendMode::Additive.blend(src, dst), LinearColor::new(0.5, 0.75, 1.0));
+        assert_eq!(BlendMode::Multiply.blend(src, dst), LinearColor::new(0.0, 0.375, 0.5));
+
+        //Fully transparent leaves the destination alone in every mode
+        let clear = Rgba::new(0.0, 0.0, 0.0, 0.0);
+        for &mode in [BlendMode::Alpha, BlendMode::Additive, BlendMode::Multiply].iter() {
+            assert_eq!(mode.blend(clear, dst), dst);
+        }
+    }
+}
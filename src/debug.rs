@@ -0,0 +1,77 @@
+use color::Color;
+use color::LinearColor;
+
+//Colors overdraw counts go through, starting at nothing drawn. Anything past the end is white
+const HEAT: [(u8, u8, u8); 6] = [
+	(0, 0, 0),
+	(0, 0, 160),
+	(0, 160, 0),
+	(220, 220, 0),
+	(230, 0, 0),
+	(255, 255, 255),
+];
+
+/*
+What the scene is drawn as, the debug views replace shading with something that shows how the
+frame was put together
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum DebugView {
+	//Normal lit and textured drawing
+	Shaded,
+
+	//How many triangles cover each pixel, as a heat map going black, blue, green, yellow, red then white
+	Overdraw,
+
+	//Distance from the camera as grayscale, white up close
+	Depth,
+
+	//Each triangle in its own color
+	TriangleId,
+
+	//Each sector in its own color
+	SectorId,
+}
+
+impl DebugView {
+	pub fn next(&self) -> DebugView {
+		match *self {
+			DebugView::Shaded => DebugView::Overdraw,
+			DebugView::Overdraw => DebugView::Depth,
+			DebugView::Depth => DebugView::TriangleId,
+			DebugView::TriangleId => DebugView::SectorId,
+			DebugView::SectorId => DebugView::Shaded,
+		}
+	}
+}
+
+//A color that is stable for an id but looks unrelated to its neighbours
+pub fn id_color(id: usize) -> LinearColor {
+	//Murmur3's finalizer, enough to scatter consecutive ids
+	let mut h = id as u32 ^ 0x9e37_79b9;
+	h ^= h >> 16;
+	h = h.wrapping_mul(0x85eb_ca6b);
+	h ^= h >> 13;
+	h = h.wrapping_mul(0xc2b2_ae35);
+	h ^= h >> 16;
+
+	//Kept away from black so nothing blends into the background
+	let channel = |bits: u32| 64 + (bits & 0xff) as u8 % 192;
+	LinearColor::from_srgb(Color::new(channel(h), channel(h >> 8), channel(h >> 16)))
+}
+
+//The heat map color for a pixel covered count times, partial counts blend between neighbours
+pub fn heat(count: f32) -> LinearColor {
+	let count = count.max(0.0).min((HEAT.len() - 1) as f32);
+	let (low, t) = (count.floor() as usize, count.fract());
+	let high = (low + 1).min(HEAT.len() - 1);
+
+	let color = |(r, g, b): (u8, u8, u8)| LinearColor::from_srgb(Color::new(r, g, b));
+	color(HEAT[low]).lerp(color(HEAT[high]), t)
+}
+
+//Linear distance from the camera as a gray level, white at the camera fading to black at range
+pub fn depth_gray(depth: f32, range: f32) -> LinearColor {
+	let level = ((1.0 - depth / range).max(0.0).min(1.0) * 255.0) as u8;
+	LinearColor::from_srgb(Color::new(level, level, level))
+}
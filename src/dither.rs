@@ -0,0 +1,322 @@
+use color::Color;
+use framebuffer::Framebuffer;
+use palette::Palette;
+
+//8x8 Bayer threshold matrix, each entry is its rank out of 64
+const BAYER: [[u8; 8]; 8] = [
+	[ 0, 32,  8, 40,  2, 34, 10, 42],
+	[48, 16, 56, 24, 50, 18, 58, 26],
+	[12, 44,  4, 36, 14, 46,  6, 38],
+	[60, 28, 52, 20, 62, 30, 54, 22],
+	[ 3, 35, 11, 43,  1, 33,  9, 41],
+	[51, 19, 59, 27, 49, 17, 57, 25],
+	[15, 47,  7, 39, 13, 45,  5, 37],
+	[63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+//The Bayer threshold for a pixel between 0 and 1, anything that wants a stable ordered pattern can use it
+pub fn ordered_threshold(x: usize, y: usize) -> f32 {
+	(BAYER[y % 8][x % 8] as f32 + 0.5) / 64.0
+}
+
+/*
+What the framebuffer is reduced to on output
+*/
+pub enum OutputFormat {
+	//Full 8 bits per channel, nothing to do
+	Rgb24,
+
+	//5 bits of red, 6 of green and 5 of blue, for small embedded displays
+	Rgb565,
+
+	//An index into the palette per pixel
+	Indexed(Palette),
+}
+
+impl OutputFormat {
+	//How far apart neighbouring output levels are, ordered dithering spreads its noise over this much
+	fn step(&self) -> (f32, f32, f32) {
+		match *self {
+			OutputFormat::Rgb24 => (1.0, 1.0, 1.0),
+			OutputFormat::Rgb565 => (255.0 / 31.0, 255.0 / 63.0, 255.0 / 31.0),
+			OutputFormat::Indexed(ref palette) => {
+				//Treats the palette as an evenly spread color cube
+				let step = 255.0 / ((palette.len() as f32).cbrt() - 1.0).max(1.0);
+				(step, step, step)
+			}
+		}
+	}
+
+	//The code written for a pixel, an RGB565 value or palette index, and the color it shows as
+	fn encode(&self, color: Color) -> (u16, Color) {
+		match *self {
+			OutputFormat::Rgb24 => (0, color),
+			OutputFormat::Rgb565 => {
+				let (r, g, b) = (to_bits(color.r(), 31), to_bits(color.g(), 63), to_bits(color.b(), 31));
+				(r << 11 | g << 5 | b, Color::new(from_bits(r, 31), from_bits(g, 63), from_bits(b, 31)))
+			}
+			OutputFormat::Indexed(ref palette) => {
+				let index = palette.nearest(color);
+				(index as u16, palette.color(index))
+			}
+		}
+	}
+}
+
+fn to_bits(c: u8, max: u16) -> u16 {
+	(c as u16 * max + 127) / 255
+}
+
+fn from_bits(c: u16, max: u16) -> u8 {
+	((c * 255 + max / 2) / max) as u8
+}
+
+/*
+How the error from reducing colors gets hidden
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Dither {
+	//Rounds every pixel to the nearest color, flat areas band
+	None,
+
+	//Adds a repeating Bayer pattern before rounding, stable from frame to frame
+	Ordered,
+
+	//Floyd-Steinberg, pushes each pixel's rounding error onto the pixels after it
+	ErrorDiffusion,
+}
+
+impl Dither {
+	pub fn next(&self) -> Dither {
+		match *self {
+			Dither::None => Dither::Ordered,
+			Dither::Ordered => Dither::ErrorDiffusion,
+			Dither::ErrorDiffusion => Dither::None,
+		}
+	}
+}
+
+/*
+Reduces the resolved framebuffer to an output format. The pixels are overwritten with the colors
+they will show as, and the codes for the target display are kept in codes()
+*/
+pub struct Quantizer {
+	format: OutputFormat,
+	dither: Dither,
+
+	codes: Vec<u16>,
+
+	//Error carried onto this row and the next by error diffusion
+	errors: Vec<(f32, f32, f32)>,
+	next_errors: Vec<(f32, f32, f32)>,
+}
+
+impl Quantizer {
+	pub fn new(format: OutputFormat, dither: Dither) -> Quantizer {
+		Quantizer {
+			format: format,
+			dither: dither,
+			codes: Vec::new(),
+			errors: Vec::new(),
+			next_errors: Vec::new(),
+		}
+	}
+
+	pub fn set_format(&mut self, format: OutputFormat) {
+		self.format = format;
+	}
+
+	pub fn format(&self) -> &OutputFormat {
+		&self.format
+	}
+
+	pub fn set_dither(&mut self, dither: Dither) {
+		self.dither = dither;
+	}
+
+	pub fn dither(&self) -> Dither {
+		self.dither
+	}
+
+	//One RGB565 value or palette index per pixel from the last apply(), in rows from the top.
+	//Empty for Rgb24, the framebuffer's pixels are already the output
+	pub fn codes(&self) -> &[u16] {
+		&self.codes
+	}
+
+	//Quantizes what the framebuffer last resolved to
+	pub fn apply(&mut self, framebuffer: &mut Framebuffer) {
+		let (width, height) = (framebuffer.width(), framebuffer.height());
+		let pixels = framebuffer.pixels_mut();
+
+		self.codes.clear();
+		if let OutputFormat::Rgb24 = self.format {
+			return;
+		}
+
+		self.errors.clear();
+		self.errors.resize(width + 2, (0.0, 0.0, 0.0));
+		self.next_errors.clear();
+		self.next_errors.resize(width + 2, (0.0, 0.0, 0.0));
+
+		let step = self.format.step();
+		let channel = |c: f32| (c + 0.5).max(0.0).min(255.0) as u8;
+
+		for y in 0..height {
+			for x in 0..width {
+				let offset = (x + y * width) * 3;
+				let (r, g, b) = (pixels[offset] as f32, pixels[offset + 1] as f32, pixels[offset + 2] as f32);
+
+				let wanted = match self.dither {
+					Dither::None => (r, g, b),
+					Dither::Ordered => {
+						let threshold = ordered_threshold(x, y) - 0.5;
+						(r + threshold * step.0, g + threshold * step.1, b + threshold * step.2)
+					}
+					//Errors are offset by one so the pixel left of the first has somewhere to go
+					Dither::ErrorDiffusion => {
+						let error = self.errors[x + 1];
+						(r + error.0, g + error.1, b + error.2)
+					}
+				};
+
+				let wanted = Color::new(channel(wanted.0), channel(wanted.1), channel(wanted.2));
+				let (code, shown) = self.format.encode(wanted);
+				self.codes.push(code);
+				pixels[offset] = shown.r();
+				pixels[offset + 1] = shown.g();
+				pixels[offset + 2] = shown.b();
+
+				if self.dither == Dither::ErrorDiffusion {
+					let error = (wanted.r() as f32 - shown.r() as f32,
+								 wanted.g() as f32 - shown.g() as f32,
+								 wanted.b() as f32 - shown.b() as f32);
+					let spread = |e: &mut (f32, f32, f32), weight: f32| {
+						e.0 += error.0 * weight;
+						e.1 += error.1 * weight;
+						e.2 += error.2 * weight;
+					};
+
+					spread(&mut self.errors[x + 2], 7.0 / 16.0);
+					spread(&mut self.next_errors[x], 3.0 / 16.0);
+					spread(&mut self.next_errors[x + 1], 5.0 / 16.0);
+					spread(&mut self.next_errors[x + 2], 1.0 / 16.0);
+				}
+			}
+
+			::std::mem::swap(&mut self.errors, &mut self.next_errors);
+			for error in self.next_errors.iter_mut() {
+				*error = (0.0, 0.0, 0.0);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	//A framebuffer whose resolved pixels are already filled in
+	fn framebuffer(width: usize, height: usize, f: &dyn Fn(usize, usize) -> Color) -> Framebuffer {
+		let mut framebuffer = Framebuffer::new(width, height);
+		for (i, pixel) in framebuffer.pixels_mut().chunks_mut(3).enumerate() {
+			let color = f(i % width, i / width);
+			pixel.copy_from_slice(&[color.r(), color.g(), color.b()]);
+		}
+		framebuffer
+	}
+
+	#[test]
+	fn rgb565_packs_channel_extremes() {
+		let format = OutputFormat::Rgb565;
+		let white = Color::new(255, 255, 255);
+
+		assert_eq!(format.encode(Color::new(0, 0, 0)), (0x0000, Color::new(0, 0, 0)));
+		assert_eq!(format.encode(white), (0xffff, white));
+		assert_eq!(format.encode(Color::new(255, 0, 0)), (0xf800, Color::new(255, 0, 0)));
+		assert_eq!(format.encode(Color::new(0, 255, 0)), (0x07e0, Color::new(0, 255, 0)));
+		assert_eq!(format.encode(Color::new(0, 0, 255)), (0x001f, Color::new(0, 0, 255)));
+	}
+
+	#[test]
+	fn rgb565_levels_round_trip() {
+		for &max in [31, 63].iter() {
+			for level in 0..(max + 1) {
+				assert_eq!(to_bits(from_bits(level, max), max), level);
+			}
+		}
+	}
+
+	#[test]
+	fn ordered_threshold_covers_the_bayer_range() {
+		let mut thresholds: Vec<f32> = Vec::new();
+		for y in 0..8 {
+			for x in 0..8 {
+				let threshold = ordered_threshold(x, y);
+				assert!(threshold > 0.0 && threshold < 1.0);
+
+				//The same wherever the pattern repeats
+				assert_eq!(threshold, ordered_threshold(x + 8, y));
+				assert_eq!(threshold, ordered_threshold(x + 24, y + 16));
+				thresholds.push(threshold);
+			}
+		}
+
+		//Every rank once, evenly spaced
+		thresholds.sort_by(|a, b| a.partial_cmp(b).unwrap());
+		for (rank, &threshold) in thresholds.iter().enumerate() {
+			assert_eq!(threshold, (rank as f32 + 0.5) / 64.0);
+		}
+	}
+
+	#[test]
+	fn error_diffusion_keeps_the_mean_of_a_flat_field() {
+		//A gray between RGB565 levels on every channel
+		let gray = Color::new(100, 100, 100);
+		let (width, height) = (64, 64);
+		let mut fb = framebuffer(width, height, &|_, _| gray);
+
+		let mut quantizer = Quantizer::new(OutputFormat::Rgb565, Dither::ErrorDiffusion);
+		quantizer.apply(&mut fb);
+
+		let pixels = fb.pixels();
+		for channel in 0..3 {
+			let sum: f32 = pixels.iter().skip(channel).step_by(3).map(|&c| c as f32).sum();
+			let mean = sum / (width * height) as f32;
+			assert!((mean - 100.0).abs() < 0.5, "channel {} averages {}", channel, mean);
+		}
+
+		//Plain rounding would have left it flat at the nearest level
+		let (_, nearest) = OutputFormat::Rgb565.encode(gray);
+		assert!(nearest != gray);
+		assert!(pixels.chunks(3).any(|p| p[0] != nearest.r()));
+	}
+
+	#[test]
+	fn no_dither_is_plain_quantization() {
+		let color = |x: usize, y: usize| Color::new((x * 37 + y * 11) as u8, (x * 5 + y * 71) as u8, (x * y) as u8);
+		let (width, height) = (20, 12);
+
+		for format in vec![OutputFormat::Rgb565, OutputFormat::Indexed(Palette::rgb332())] {
+			let mut fb = framebuffer(width, height, &color);
+			let mut quantizer = Quantizer::new(format, Dither::None);
+			quantizer.apply(&mut fb);
+
+			for y in 0..height {
+				for x in 0..width {
+					let (code, shown) = quantizer.format().encode(color(x, y));
+					let i = x + y * width;
+					assert_eq!(quantizer.codes()[i], code);
+					assert_eq!(&fb.pixels()[i * 3..i * 3 + 3], &[shown.r(), shown.g(), shown.b()]);
+				}
+			}
+		}
+
+		//Rgb24 has nothing to reduce
+		let mut fb = framebuffer(width, height, &color);
+		let mut quantizer = Quantizer::new(OutputFormat::Rgb24, Dither::None);
+		quantizer.apply(&mut fb);
+		assert!(quantizer.codes().is_empty());
+		assert_eq!(fb.pixels(), framebuffer(width, height, &color).pixels());
+	}
+}
@@ -0,0 +1,144 @@
+use color::Color;
+use color::LinearColor;
+
+//Fog this thick or more is treated as solid, 1/255 is the smallest step a color channel can show
+const VISIBILITY_CUTOFF: f32 = 1.0 / 255.0;
+
+/*
+How quickly fog builds up with distance
+*/
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FogMode {
+	//Clear up to start and solid from end on
+	Linear { start: f32, end: f32 },
+
+	//e^-(density*d)
+	Exponential { density: f32 },
+
+	//e^-(density*d)^2, stays clear for longer then closes in quickly
+	ExponentialSquared { density: f32 },
+}
+
+/*
+Distance fog blended over each pixel by its depth in front of the camera
+*/
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Fog {
+	mode: FogMode,
+	color: LinearColor,
+}
+
+impl Fog {
+	pub fn new(mode: FogMode, color: LinearColor) -> Fog {
+		Fog {
+			mode: mode,
+			color: color,
+		}
+	}
+
+	//Parses the arguments of an NMF fog line:
+	//	fog linear <start> <end> <r> <g> <b>
+	//	fog exp <density> <r> <g> <b>
+	//	fog exp2 <density> <r> <g> <b>
+	//The color is 0-255 sRGB like the rest of the map
+	pub fn parse(args: &str) -> Option<Fog> {
+		let mut words = args.split_whitespace();
+		let kind = words.next()?;
+		let numbers: Vec<f32> = words.map(|w| w.parse::<f32>().ok()).collect::<Option<Vec<f32>>>()?;
+
+		let (mode, rgb) = match (kind, numbers.len()) {
+			("linear", 5) => (FogMode::Linear { start: numbers[0], end: numbers[1] }, &numbers[2..]),
+			("exp", 4) => (FogMode::Exponential { density: numbers[0] }, &numbers[1..]),
+			("exp2", 4) => (FogMode::ExponentialSquared { density: numbers[0] }, &numbers[1..]),
+			_ => return None,
+		};
+
+		let channel = |c: f32| c.clamp(0.0, 255.0) as u8;
+		Some(Fog::new(mode, LinearColor::from_srgb(Color::new(channel(rgb[0]), channel(rgb[1]), channel(rgb[2])))))
+	}
+
+	pub fn mode(&self) -> FogMode {
+		self.mode
+	}
+
+	pub fn color(&self) -> LinearColor {
+		self.color
+	}
+
+	//How much of the surface is still visible at depth, 1 is clear and 0 is solid fog
+	pub fn visibility(&self, depth: f32) -> f32 {
+		let depth = depth.max(0.0);
+
+		let visibility = match self.mode {
+			FogMode::Linear { start, end } => {
+				if end <= start {
+					if depth < start { 1.0 } else { 0.0 }
+				} else {
+					(end - depth) / (end - start)
+				}
+			}
+			FogMode::Exponential { density } => (-density * depth).exp(),
+			FogMode::ExponentialSquared { density } => (-(density * depth) * (density * depth)).exp(),
+		};
+
+		visibility.clamp(0.0, 1.0)
+	}
+
+	//Distance past which everything is hidden, anything further away doesn't need drawing
+	pub fn max_distance(&self) -> f32 {
+		match self.mode {
+			FogMode::Linear { end, .. } => end,
+			FogMode::Exponential { density } => -VISIBILITY_CUTOFF.ln() / density,
+			FogMode::ExponentialSquared { density } => (-VISIBILITY_CUTOFF.ln()).sqrt() / density,
+		}
+	}
+
+	pub fn apply(&self, color: LinearColor, depth: f32) -> LinearColor {
+		color.lerp(self.color, 1.0 - self.visibility(depth))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn srgb(r: u8, g: u8, b: u8) -> LinearColor {
+		LinearColor::from_srgb(Color::new(r, g, b))
+	}
+
+	#[test]
+	fn parses_each_mode() {
+		assert_eq!(Fog::parse("linear 10 50 128 128 128"), Some(Fog::new(FogMode::Linear { start: 10.0, end: 50.0 }, srgb(128, 128, 128))));
+		assert_eq!(Fog::parse("exp 0.05 0 0 255"), Some(Fog::new(FogMode::Exponential { density: 0.05 }, srgb(0, 0, 255))));
+		assert_eq!(Fog::parse("  exp2  0.1 255 0 0 "), Some(Fog::new(FogMode::ExponentialSquared { density: 0.1 }, srgb(255, 0, 0))));
+	}
+
+	#[test]
+	fn clamps_the_color() {
+		assert_eq!(Fog::parse("exp 0.05 -20 300 128").map(|fog| fog.color()), Some(srgb(0, 255, 128)));
+	}
+
+	#[test]
+	fn rejects_malformed_lines() {
+		assert_eq!(Fog::parse(""), None);
+		assert_eq!(Fog::parse("smoke 0.05 0 0 0"), None);
+		assert_eq!(Fog::parse("linear 10 50 128 128"), None);
+		assert_eq!(Fog::parse("exp 0.05 0 0 0 0"), None);
+		assert_eq!(Fog::parse("exp2 thick 0 0 0"), None);
+	}
+
+	#[test]
+	fn visibility_falls_off_with_depth() {
+		let linear = Fog::new(FogMode::Linear { start: 10.0, end: 50.0 }, srgb(0, 0, 0));
+		assert_eq!(linear.visibility(5.0), 1.0);
+		assert_eq!(linear.visibility(30.0), 0.5);
+		assert_eq!(linear.visibility(60.0), 0.0);
+
+		for &mode in [FogMode::Exponential { density: 0.05 }, FogMode::ExponentialSquared { density: 0.05 }].iter() {
+			let fog = Fog::new(mode, srgb(0, 0, 0));
+			assert_eq!(fog.visibility(0.0), 1.0);
+			assert!(fog.visibility(10.0) < 1.0 && fog.visibility(10.0) > fog.visibility(20.0));
+			assert!(fog.visibility(fog.max_distance()) <= VISIBILITY_CUTOFF * 1.001);
+		}
+	}
+}
@@ -0,0 +1,183 @@
+use color::Color;
+use framebuffer::Framebuffer;
+
+//Size of a glyph in pixels
+pub const GLYPH_WIDTH: usize = 5;
+pub const GLYPH_HEIGHT: usize = 7;
+
+//How far the pen moves after a character and after a line, leaving a gap between them
+pub const ADVANCE: usize = GLYPH_WIDTH + 1;
+pub const LINE_HEIGHT: usize = GLYPH_HEIGHT + 2;
+
+//The first character in GLYPHS, they run through to '~'
+const FIRST: u8 = b' ';
+
+//A 5x7 font covering printable ASCII. Each glyph is five columns from left to right, with the top
+//row in the lowest bit
+static GLYPHS: [[u8; GLYPH_WIDTH]; 95] = [
+	[0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+	[0x00, 0x00, 0x5f, 0x00, 0x00], // '!'
+	[0x00, 0x07, 0x00, 0x07, 0x00], // '"'
+	[0x14, 0x7f, 0x14, 0x7f, 0x14], // '#'
+	[0x24, 0x2a, 0x7f, 0x2a, 0x12], // '$'
+	[0x23, 0x13, 0x08, 0x64, 0x62], // '%'
+	[0x36, 0x49, 0x55, 0x22, 0x50], // '&'
+	[0x00, 0x05, 0x03, 0x00, 0x00], // '\''
+	[0x00, 0x1c, 0x22, 0x41, 0x00], // '('
+	[0x00, 0x41, 0x22, 0x1c, 0x00], // ')'
+	[0x14, 0x08, 0x3e, 0x08, 0x14], // '*'
+	[0x08, 0x08, 0x3e, 0x08, 0x08], // '+'
+	[0x00, 0x50, 0x30, 0x00, 0x00], // ','
+	[0x08, 0x08, 0x08, 0x08, 0x08], // '-'
+	[0x00, 0x60, 0x60, 0x00, 0x00], // '.'
+	[0x20, 0x10, 0x08, 0x04, 0x02], // '/'
+	[0x3e, 0x51, 0x49, 0x45, 0x3e], // '0'
+	[0x00, 0x42, 0x7f, 0x40, 0x00], // '1'
+	[0x42, 0x61, 0x51, 0x49, 0x46], // '2'
+	[0x21, 0x41, 0x45, 0x4b, 0x31], // '3'
+	[0x18, 0x14, 0x12, 0x7f, 0x10], // '4'
+	[0x27, 0x45, 0x45, 0x45, 0x39], // '5'
+	[0x3c, 0x4a, 0x49, 0x49, 0x30], // '6'
+	[0x01, 0x71, 0x09, 0x05, 0x03], // '7'
+	[0x36, 0x49, 0x49, 0x49, 0x36], // '8'
+	[0x06, 0x49, 0x49, 0x29, 0x1e], // '9'
+	[0x00, 0x36, 0x36, 0x00, 0x00], // ':'
+	[0x00, 0x56, 0x36, 0x00, 0x00], // ';'
+	[0x08, 0x14, 0x22, 0x41, 0x00], // '<'
+	[0x14, 0x14, 0x14, 0x14, 0x14], // '='
+	[0x00, 0x41, 0x22, 0x14, 0x08], // '>'
+	[0x02, 0x01, 0x51, 0x09, 0x06], // '?'
+	[0x32, 0x49, 0x79, 0x41, 0x3e], // '@'
+	[0x7e, 0x11, 0x11, 0x11, 0x7e], // 'A'
+	[0x7f, 0x49, 0x49, 0x49, 0x36], // 'B'
+	[0x3e, 0x41, 0x41, 0x41, 0x22], // 'C'
+	[0x7f, 0x41, 0x41, 0x22, 0x1c], // 'D'
+	[0x7f, 0x49, 0x49, 0x49, 0x41], // 'E'
+	[0x7f, 0x09, 0x09, 0x09, 0x01], // 'F'
+	[0x3e, 0x41, 0x49, 0x49, 0x7a], // 'G'
+	[0x7f, 0x08, 0x08, 0x08, 0x7f], // 'H'
+	[0x00, 0x41, 0x7f, 0x41, 0x00], // 'I'
+	[0x20, 0x40, 0x41, 0x3f, 0x01], // 'J'
+	[0x7f, 0x08, 0x14, 0x22, 0x41], // 'K'
+	[0x7f, 0x40, 0x40, 0x40, 0x40], // 'L'
+	[0x7f, 0x02, 0x0c, 0x02, 0x7f], // 'M'
+	[0x7f, 0x04, 0x08, 0x10, 0x7f], // 'N'
+	[0x3e, 0x41, 0x41, 0x41, 0x3e], // 'O'
+	[0x7f, 0x09, 0x09, 0x09, 0x06], // 'P'
+	[0x3e, 0x41, 0x51, 0x21, 0x5e], // 'Q'
+	[0x7f, 0x09, 0x19, 0x29, 0x46], // 'R'
+	[0x46, 0x49, 0x49, 0x49, 0x31], // 'S'
+	[0x01, 0x01, 0x7f, 0x01, 0x01], // 'T'
+	[0x3f, 0x40, 0x40, 0x40, 0x3f], // 'U'
+	[0x1f, 0x20, 0x40, 0x20, 0x1f], // 'V'
+	[0x3f, 0x40, 0x38, 0x40, 0x3f], // 'W'
+	[0x63, 0x14, 0x08, 0x14, 0x63], // 'X'
+	[0x07, 0x08, 0x70, 0x08, 0x07], // 'Y'
+	[0x61, 0x51, 0x49, 0x45, 0x43], // 'Z'
+	[0x00, 0x7f, 0x41, 0x41, 0x00], // '['
+	[0x02, 0x04, 0x08, 0x10, 0x20], // '\\'
+	[0x00, 0x41, 0x41, 0x7f, 0x00], // ']'
+	[0x04, 0x02, 0x01, 0x02, 0x04], // '^'
+	[0x40, 0x40, 0x40, 0x40, 0x40], // '_'
+	[0x00, 0x01, 0x02, 0x04, 0x00], // '`'
+	[0x20, 0x54, 0x54, 0x54, 0x78], // 'a'
+	[0x7f, 0x48, 0x44, 0x44, 0x38], // 'b'
+	[0x38, 0x44, 0x44, 0x44, 0x20], // 'c'
+	[0x38, 0x44, 0x44, 0x48, 0x7f], // 'd'
+	[0x38, 0x54, 0x54, 0x54, 0x18], // 'e'
+	[0x08, 0x7e, 0x09, 0x01, 0x02], // 'f'
+	[0x0c, 0x52, 0x52, 0x52, 0x3e], // 'g'
+	[0x7f, 0x08, 0x04, 0x04, 0x78], // 'h'
+	[0x00, 0x44, 0x7d, 0x40, 0x00], // 'i'
+	[0x20, 0x40, 0x44, 0x3d, 0x00], // 'j'
+	[0x7f, 0x10, 0x28, 0x44, 0x00], // 'k'
+	[0x00, 0x41, 0x7f, 0x40, 0x00], // 'l'
+	[0x7c, 0x04, 0x18, 0x04, 0x78], // 'm'
+	[0x7c, 0x08, 0x04, 0x04, 0x78], // 'n'
+	[0x38, 0x44, 0x44, 0x44, 0x38], // 'o'
+	[0x7c, 0x14, 0x14, 0x14, 0x08], // 'p'
+	[0x08, 0x14, 0x14, 0x18, 0x7c], // 'q'
+	[0x7c, 0x08, 0x04, 0x04, 0x08], // 'r'
+	[0x48, 0x54, 0x54, 0x54, 0x20], // 's'
+	[0x04, 0x3f, 0x44, 0x40, 0x20], // 't'
+	[0x3c, 0x40, 0x40, 0x20, 0x7c], // 'u'
+	[0x1c, 0x20, 0x40, 0x20, 0x1c], // 'v'
+	[0x3c, 0x40, 0x30, 0x40, 0x3c], // 'w'
+	[0x44, 0x28, 0x10, 0x28, 0x44], // 'x'
+	[0x0c, 0x50, 0x50, 0x50, 0x3c], // 'y'
+	[0x44, 0x64, 0x54, 0x4c, 0x44], // 'z'
+	[0x00, 0x08, 0x36, 0x41, 0x00], // '{'
+	[0x00, 0x00, 0x7f, 0x00, 0x00], // '|'
+	[0x00, 0x41, 0x36, 0x08, 0x00], // '}'
+	[0x08, 0x04, 0x08, 0x10, 0x08], // '~'
+];
+
+//Characters outside printable ASCII are drawn as '?'
+fn glyph(c: char) -> &'static [u8; GLYPH_WIDTH] {
+	let c = c as u32;
+	if c >= FIRST as u32 && c < FIRST as u32 + GLYPHS.len() as u32 {
+		&GLYPHS[(c - FIRST as u32) as usize]
+	} else {
+		&GLYPHS[(b'?' - FIRST) as usize]
+	}
+}
+
+//Width and height in pixels of text as draw_text_scaled() would lay it out
+pub fn text_size(text: &str, scale: usize) -> (usize, usize) {
+	let lines = text.split('\n');
+	let width = lines.clone().map(|line| line.chars().count() * ADVANCE).max().unwrap_or(0);
+	let height = lines.count() * LINE_HEIGHT;
+	(width.saturating_sub(ADVANCE - GLYPH_WIDTH) * scale, (height - (LINE_HEIGHT - GLYPH_HEIGHT)) * scale)
+}
+
+//Writes text straight into the framebuffer's resolved pixels with its top left corner at x, y, so it
+//goes on after resolve() and isn't touched by tone mapping or dithering. '\n' starts a new line and
+//anything off the edge is clipped
+pub fn draw_text(framebuffer: &mut Framebuffer, x: usize, y: usize, text: &str, color: Color) {
+	draw_text_scaled(framebuffer, x, y, text, color, 1);
+}
+
+//Like draw_text but every font pixel becomes a scale x scale block
+pub fn draw_text_scaled(framebuffer: &mut Framebuffer, x: usize, y: usize, text: &str, color: Color, scale: usize) {
+	let (width, height, pitch) = (framebuffer.width(), framebuffer.height(), framebuffer.pitch());
+	let pixels = framebuffer.pixels_mut();
+
+	for (line, text) in text.split('\n').enumerate() {
+		for (column, c) in text.chars().enumerate() {
+			let (left, top) = (x + column * ADVANCE * scale, y + line * LINE_HEIGHT * scale);
+
+			for (gx, bits) in glyph(c).iter().enumerate() {
+				for gy in 0..GLYPH_HEIGHT {
+					if bits & (1 << gy) == 0 {
+						continue;
+					}
+
+					let (px, py) = (left + gx * scale, top + gy * scale);
+					for py in py..(py + scale).min(height) {
+						for px in px..(px + scale).min(width) {
+							let offset = px * 3 + py * pitch;
+							pixels[offset] = color.r();
+							pixels[offset + 1] = color.g();
+							pixels[offset + 2] = color.b();
+						}
+					}
+				}
+			}
+		}
+	}
+}
+
+//Darkens the resolved pixels in a box so text drawn over it stands out from the scene
+pub fn darken_box(framebuffer: &mut Framebuffer, x: usize, y: usize, width: usize, height: usize) {
+	let (right, bottom, pitch) = ((x + width).min(framebuffer.width()), (y + height).min(framebuffer.height()), framebuffer.pitch());
+	if x >= right {
+		return;
+	}
+
+	let pixels = framebuffer.pixels_mut();
+	for row in y..bottom {
+		for value in pixels[(x * 3 + row * pitch)..(right * 3 + row * pitch)].iter_mut() {
+			*value /= 3;
+		}
+	}
+}
@@ -0,0 +1,264 @@
+use color::LinearColor;
+use color::BlendMode;
+use color::Rgba;
+use color::ToneMapper;
+use color::SrgbEncoder;
+use palette::Palette;
+use simd;
+
+/*
+A software render target with a linear light color buffer and a depth buffer. Shading accumulates
+in floating point and resolve() tone maps it into the RGB24 pixels that get displayed.
+A tile covers part of a larger target, it is addressed with the larger target's coordinates.
+With multisampling every pixel has several samples of color and depth that resolve() averages
+*/
+pub struct Framebuffer {
+	left: usize,
+	top: usize,
+	width: usize,
+	height: usize,
+
+	//Samples per pixel, color and depth hold a width x height plane for each one
+	samples: usize,
+
+	//Color and pixels are empty in a depth_only() target
+	color: Vec<LinearColor>,
+
+	//sRGB encoded output of the last resolve()
+	pixels: Vec<u8>,
+	encoder: SrgbEncoder,
+
+	//Stores 1/w, so larger values are closer and 0 is infinitely far away
+	depth: Vec<f32>,
+}
+
+impl Framebuffer {
+	pub fn new(width: usize, height: usize) -> Framebuffer {
+		Framebuffer::tile(0, 0, width, height)
+	}
+
+	//The width x height part of a target with its top left corner at left, top
+	pub fn tile(left: usize, top: usize, width: usize, height: usize) -> Framebuffer {
+		Framebuffer {
+			left: left,
+			top: top,
+			width: width,
+			height: height,
+			samples: 1,
+			color: vec![LinearColor::black(); width * height],
+			pixels: vec![0; width * height * 3],
+			encoder: SrgbEncoder::new(),
+			depth: vec![0.0; width * height],
+		}
+	}
+
+	//A target with only a depth buffer, for depth passes like shadow maps that never need color.
+	//Only draw_triangle_depth() can draw into it
+	pub fn depth_only(width: usize, height: usize) -> Framebuffer {
+		Framebuffer {
+			left: 0,
+			top: 0,
+			width: width,
+			height: height,
+			samples: 1,
+			color: Vec::new(),
+			pixels: Vec::new(),
+			encoder: SrgbEncoder::new(),
+			depth: vec![0.0; width * height],
+		}
+	}
+
+	fn has_color(&self) -> bool {
+		!self.pixels.is_empty()
+	}
+
+	//Reallocates the color and depth buffers if the sample count changes, clearing them
+	pub fn set_samples(&mut self, samples: usize) {
+		if samples == self.samples {
+			return;
+		}
+
+		let size = self.width * self.height * samples;
+		self.samples = samples;
+		if self.has_color() {
+			self.color = vec![LinearColor::black(); size];
+		}
+		self.depth = vec![0.0; size];
+	}
+
+	pub fn samples(&self) -> usize {
+		self.samples
+	}
+
+	pub fn clear(&mut self, color: LinearColor) {
+		for pixel in self.color.iter_mut() {
+			*pixel = color;
+		}
+
+		simd::fill(&mut self.depth, 0.0);
+	}
+
+	fn index(&self, x: usize, y: usize) -> usize {
+		(x - self.left) + (y - self.top) * self.width
+	}
+
+	//Where a sample's plane starts in color and depth
+	fn plane(&self, sample: usize) -> usize {
+		sample * self.width * self.height
+	}
+
+	//Passes if inv_w is closer than what the sample already has, and stores it if write is set
+	pub fn depth_test(&mut self, x: usize, y: usize, sample: usize, inv_w: f32, write: bool) -> bool {
+		let index = self.index(x, y) + self.plane(sample);
+		if inv_w <= self.depth[index] {
+			return false;
+		}
+
+		if write {
+			self.depth[index] = inv_w;
+		}
+		true
+	}
+
+	//Depth of the first sample
+	pub fn depth(&self, x: usize, y: usize) -> f32 {
+		self.depth[self.index(x, y)]
+	}
+
+	//Pixels with something drawn into their first sample's depth since the last clear
+	pub fn covered_pixels(&self) -> usize {
+		self.depth[..(self.width * self.height)].iter().filter(|&&depth| depth > 0.0).count()
+	}
+
+	//Pixels whose first sample's color passes f
+	pub fn count_colors<F: Fn(LinearColor) -> bool>(&self, f: F) -> usize {
+		self.color[..(self.width * self.height)].iter().filter(|&&color| f(color)).count()
+	}
+
+	//count depths of a sample along a row starting at x, y
+	pub fn depth_span(&self, x: usize, y: usize, sample: usize, count: usize) -> &[f32] {
+		let index = self.index(x, y) + self.plane(sample);
+		&self.depth[index..(index + count)]
+	}
+
+	//Sets every sample of a pixel
+	pub fn set_pixel(&mut self, x: usize, y: usize, color: LinearColor) {
+		self.set_samples_masked(x, y, !0, color);
+	}
+
+	//Sets the samples of a pixel that have their bit set in mask
+	pub fn set_samples_masked(&mut self, x: usize, y: usize, mask: u32, color: LinearColor) {
+		let index = self.index(x, y);
+		for sample in 0..self.samples {
+			if mask & (1 << sample) != 0 {
+				let plane = self.plane(sample);
+				self.color[index + plane] = color;
+			}
+		}
+	}
+
+	//Sets a sample of a row of pixels starting at x, y to colors, for the pixels with their bit set in mask
+	pub fn set_span_masked(&mut self, x: usize, y: usize, sample: usize, colors: &[LinearColor], mask: u64) {
+		let index = self.index(x, y) + self.plane(sample);
+		let count = colors.len().min(self.left + self.width - x);
+
+		simd::store_colors(&mut self.color[index..(index + count)], &colors[..count], mask);
+	}
+
+	//Color of the first sample
+	pub fn pixel(&self, x: usize, y: usize) -> LinearColor {
+		self.color[self.index(x, y)]
+	}
+
+	//Blends a premultiplied color over the samples of a pixel in mask
+	pub fn blend_samples_masked(&mut self, x: usize, y: usize, mask: u32, color: Rgba, mode: BlendMode) {
+		let index = self.index(x, y);
+		for sample in 0..self.samples {
+			if mask & (1 << sample) != 0 {
+				let index = index + self.plane(sample);
+				self.color[index] = mode.blend(color, self.color[index]);
+			}
+		}
+	}
+
+	//Replaces every sample's color, for views that recolor the frame before it is resolved
+	pub fn map_colors<F: Fn(LinearColor) -> LinearColor>(&mut self, f: F) {
+		for color in self.color.iter_mut() {
+			*color = f(*color);
+		}
+	}
+
+	//Tone maps the color buffer and encodes it to sRGB for pixels(), exposure scales the light first.
+	//Samples are tone mapped before they are averaged so very bright ones don't swamp an edge
+	pub fn resolve(&mut self, tone_mapper: ToneMapper, exposure: f32) {
+		let (pixel_count, samples) = (self.width * self.height, self.samples);
+		let inv_samples = 1.0 / samples as f32;
+
+		for (index, pixel) in self.pixels.chunks_mut(3).enumerate() {
+			let mut color = tone_mapper.map(self.color[index] * exposure);
+			for sample in 1..samples {
+				color = color + tone_mapper.map(self.color[index + sample * pixel_count] * exposure);
+			}
+
+			let encoded = self.encoder.encode_color(color * inv_samples);
+			pixel[0] = encoded.r();
+			pixel[1] = encoded.g();
+			pixel[2] = encoded.b();
+		}
+	}
+
+	//Copies a tile's color and depth into the part of this framebuffer it covers, both need the same
+	//number of samples
+	pub fn blit(&mut self, tile: &Framebuffer) {
+		for sample in 0..self.samples {
+			let (from_plane, to_plane) = (tile.plane(sample), self.plane(sample));
+
+			for y in 0..tile.height {
+				let from = y * tile.width + from_plane;
+				let to = self.index(tile.left, tile.top + y) + to_plane;
+
+				self.color[to..(to + tile.width)].copy_from_slice(&tile.color[from..(from + tile.width)]);
+				self.depth[to..(to + tile.width)].copy_from_slice(&tile.depth[from..(from + tile.width)]);
+			}
+		}
+	}
+
+	//Shows a frame of palette indices drawn with draw_triangle_indexed() in place of the color buffer
+	pub fn resolve_indexed(&mut self, indices: &[u8], palette: &Palette) {
+		for (&index, pixel) in indices.iter().zip(self.pixels.chunks_mut(3)) {
+			let color = palette.color(index);
+			pixel[0] = color.r();
+			pixel[1] = color.g();
+			pixel[2] = color.b();
+		}
+	}
+
+	pub fn left(&self) -> usize {
+		self.left
+	}
+
+	pub fn top(&self) -> usize {
+		self.top
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	pub fn pitch(&self) -> usize {
+		self.width * 3
+	}
+
+	pub fn pixels(&self) -> &[u8] {
+		&self.pixels
+	}
+
+	//For output stages that work on the resolved pixels
+	pub fn pixels_mut(&mut self) -> &mut [u8] {
+		&mut self.pixels
+	}
+}
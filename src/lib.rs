@@ -0,0 +1,30 @@
+#[macro_use] extern crate text_io;
+
+extern crate sdl2;
+extern crate png;
+
+pub mod pointf;
+pub mod triangle;
+pub mod color;
+pub mod vertex;
+pub mod framebuffer;
+pub mod simd;
+pub mod raster;
+pub mod texture;
+pub mod mesh;
+pub mod lighting;
+pub mod fog;
+pub mod shadow;
+pub mod map;
+pub mod obj;
+pub mod tiles;
+pub mod palette;
+pub mod dither;
+pub mod font;
+pub mod stats;
+pub mod debug;
+pub mod wireframe;
+pub mod antialias;
+pub mod postprocess;
+pub mod shader;
+pub mod scene;
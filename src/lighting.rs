@@ -0,0 +1,266 @@
+use pointf::Point3f;
+use color::LinearColor;
+use shadow::ShadowMap;
+
+/*
+A light in world space, colors are linear and can go past 1 for bright lights
+*/
+#[derive(Copy, Clone, Debug)]
+pub enum Light {
+	//Lights everything evenly regardless of facing
+	Ambient {
+		color: LinearColor,
+	},
+
+	//Infinitely far away, direction is the way the light travels
+	Directional {
+		direction: Point3f,
+		color: LinearColor,
+	},
+
+	//Falls off with distance as 1 / (constant + linear*d + quadratic*d^2)
+	Point {
+		position: Point3f,
+		color: LinearColor,
+		attenuation: (f32, f32, f32),
+	},
+}
+
+impl Light {
+	pub fn color(&self) -> LinearColor {
+		match *self {
+			Light::Ambient { color } => color,
+			Light::Directional { color, .. } => color,
+			Light::Point { color, .. } => color,
+		}
+	}
+
+	//Unit direction towards the light and how much of it reaches position, None for ambient light
+	fn incident(&self, position: Point3f) -> Option<(Point3f, f32)> {
+		match *self {
+			Light::Ambient { .. } => None,
+			Light::Directional { direction, .. } => {
+				(-direction).normalize().map(|to_light| (to_light, 1.0))
+			}
+			Light::Point { position: light_position, attenuation: (constant, linear, quadratic), .. } => {
+				let offset = light_position - position;
+				let distance = offset.length();
+
+				offset.normalize().map(|to_light| {
+					(to_light, 1.0 / (constant + linear * distance + quadratic * distance * distance))
+				})
+			}
+		}
+	}
+}
+
+/*
+How a surface reflects light, specular is added on top of the lit surface color
+and shininess is the Blinn-Phong exponent, higher is a tighter highlight
+*/
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Material {
+	specular: LinearColor,
+	shininess: f32,
+}
+
+impl Material {
+	pub fn new(specular: LinearColor, shininess: f32) -> Material {
+		Material {
+			specular: specular,
+			shininess: shininess,
+		}
+	}
+
+	//A faint broad sheen
+	pub fn standard() -> Material {
+		Material::new(LinearColor::new(0.02, 0.02, 0.02), 8.0)
+	}
+
+	pub fn specular(&self) -> LinearColor {
+		self.specular
+	}
+
+	pub fn shininess(&self) -> f32 {
+		self.shininess
+	}
+}
+
+/*
+Where lighting gets evaluated, once per triangle, per vertex or per pixel
+*/
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ShadeMode {
+	Flat,
+	Gouraud,
+
+	//Per pixel, from interpolated normals and view vectors
+	Phong,
+}
+
+impl ShadeMode {
+	pub fn next(&self) -> ShadeMode {
+		match *self {
+			ShadeMode::Flat => ShadeMode::Gouraud,
+			ShadeMode::Gouraud => ShadeMode::Phong,
+			ShadeMode::Phong => ShadeMode::Flat,
+		}
+	}
+}
+
+/*
+The set of lights in a scene and how they are applied
+*/
+pub struct Lighting {
+	lights: Vec<Light>,
+	mode: ShadeMode,
+
+	//Distance at which diminishing has halved the light, None to turn it off
+	diminish: Option<f32>,
+
+	//Shadow map along with the index of the light it was rendered from
+	shadow: Option<(usize, ShadowMap)>,
+	shadows: bool,
+}
+
+impl Lighting {
+	pub fn new(mode: ShadeMode) -> Lighting {
+		Lighting {
+			lights: Vec::new(),
+			mode: mode,
+			diminish: None,
+			shadow: None,
+			shadows: true,
+		}
+	}
+
+	//Returns the index of the light so it can be moved later
+	pub fn add_light(&mut self, light: Light) -> usize {
+		self.lights.push(light);
+		self.lights.len() - 1
+	}
+
+	pub fn set_light(&mut self, index: usize, light: Light) {
+		self.lights[index] = light;
+	}
+
+	pub fn lights(&self) -> &[Light] {
+		&self.lights
+	}
+
+	pub fn set_mode(&mut self, mode: ShadeMode) {
+		self.mode = mode;
+	}
+
+	pub fn mode(&self) -> ShadeMode {
+		self.mode
+	}
+
+	//Shadows light from the given light using shadow_map, the map has to be rendered from that light
+	pub fn set_shadow_map(&mut self, light: usize, shadow_map: ShadowMap) {
+		self.shadow = Some((light, shadow_map));
+	}
+
+	pub fn set_shadows(&mut self, shadows: bool) {
+		self.shadows = shadows;
+	}
+
+	pub fn shadows(&self) -> bool {
+		self.shadows
+	}
+
+	pub fn set_diminish(&mut self, half_distance: Option<f32>) {
+		self.diminish = half_distance;
+	}
+
+	pub fn diminish_distance(&self) -> Option<f32> {
+		self.diminish
+	}
+
+	//How much light is left at depth in front of the camera, 1 when diminishing is off
+	pub fn diminish_scale(&self, depth: f32) -> f32 {
+		match self.diminish {
+			Some(half_distance) if half_distance > 0.0 => half_distance / (half_distance + depth.max(0.0)),
+			_ => 1.0,
+		}
+	}
+
+	//Darkens color the further away it is
+	pub fn diminish(&self, color: LinearColor, depth: f32) -> LinearColor {
+		color * self.diminish_scale(depth)
+	}
+
+	//Lights a surface color at position with Blinn-Phong as seen from eye, shadowed by the shadow map.
+	//Surfaces are lit from whichever side eye is on, portal walls are stored back to back and would
+	//otherwise fight over which one shows. Nothing is clamped, bright lights are left for the tone mapper
+	pub fn illuminate(&self, position: Point3f, normal: Point3f, eye: Point3f, albedo: LinearColor, material: Material) -> LinearColor {
+		let (lit, shadowed) = self.illuminate_unshadowed(position, normal, eye, albedo, material);
+
+		if shadowed == LinearColor::black() {
+			lit
+		} else {
+			lit + shadowed * self.visibility(position, normal, eye)
+		}
+	}
+
+	//Like illuminate() but with the light the shadow map was rendered from kept apart and unshadowed.
+	//Returns the other lights and that one, for lighting per vertex and looking shadows up per pixel
+	pub fn illuminate_unshadowed(&self, position: Point3f, normal: Point3f, eye: Point3f, albedo: LinearColor, material: Material) -> (LinearColor, LinearColor) {
+		let normal = match facing(normal, eye - position) {
+			Some(normal) => normal,
+			None => return (LinearColor::black(), LinearColor::black()),
+		};
+		let view = (eye - position).normalize().unwrap_or(normal);
+		let shadowed_light = match self.shadow {
+			Some((shadowed, _)) if self.shadows => Some(shadowed),
+			_ => None,
+		};
+
+		//The other lights add up in the first of each, the shadowed light in the second
+		let (mut diffuse, mut specular) = ([LinearColor::black(); 2], [LinearColor::black(); 2]);
+		for (i, light) in self.lights.iter().enumerate() {
+			let color = light.color();
+			let part = if shadowed_light == Some(i) { 1 } else { 0 };
+
+			let (to_light, intensity) = match light.incident(position) {
+				Some(incident) => incident,
+				None => {
+					diffuse[part] = diffuse[part] + color;
+					continue;
+				}
+			};
+
+			let lambert = normal.dot(to_light);
+			if lambert <= 0.0 || intensity <= 0.0 {
+				continue;
+			}
+
+			diffuse[part] = diffuse[part] + color * (lambert * intensity);
+
+			//Blinn-Phong uses the half vector instead of reflecting the light
+			let s = match (to_light + view).normalize() {
+				Some(half) => normal.dot(half).max(0.0).powf(material.shininess()) * intensity,
+				None => 0.0,
+			};
+			specular[part] = specular[part] + color * s;
+		}
+
+		(albedo * diffuse[0] + material.specular() * specular[0], albedo * diffuse[1] + material.specular() * specular[1])
+	}
+
+	//How much of the shadowed light reaches position, 1 without a shadow map or with shadows off
+	pub fn visibility(&self, position: Point3f, normal: Point3f, eye: Point3f) -> f32 {
+		match (&self.shadow, facing(normal, eye - position)) {
+			(&Some((_, ref shadow_map)), Some(normal)) if self.shadows => shadow_map.visibility(position, normal),
+			_ => 1.0,
+		}
+	}
+}
+
+//The normal turned to face view
+fn facing(normal: Point3f, view: Point3f) -> Option<Point3f> {
+	match normal.normalize() {
+		Some(normal) if normal.dot(view) < 0.0 => Some(-normal),
+		normal => normal,
+	}
+}
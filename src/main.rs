@@ -1,24 +1,57 @@
 #[macro_use] extern crate unborrow;
-#[macro_use] extern crate text_io;
 
 extern crate sdl2;
 use sdl2::*;
 use sdl2::rect::Point;
 use std::error::Error;
-use std::fs::File;
 use std::path::Path;
-use std::io::BufRead;
-use std::io::BufReader;
 use std::io::prelude;
 
-mod pointf;
-use pointf::*;
+extern crate rasterizer;
+use rasterizer::pointf::*;
+use rasterizer::triangle::*;
+use rasterizer::vertex::Attributes;
+use rasterizer::map::Map;
+use rasterizer::obj::export_obj;
+use rasterizer::tiles::TileRenderer;
+use rasterizer::palette::Palette;
+use rasterizer::postprocess::PostProcess;
+use rasterizer::dither::OutputFormat;
+use rasterizer::scene::{Scene, DIMINISH_DISTANCE};
+use rasterizer::color::{Color, LinearColor};
+use rasterizer::raster::Fragment;
+use rasterizer::shader::VertexShader;
+use rasterizer::mesh::MeshVertex;
+use rasterizer::font::{draw_text_scaled, text_size, darken_box};
+use rasterizer::debug::DebugView;
+use rasterizer::wireframe::WireframeMode;
+
+//Size of the HUD's text, each font pixel covers HUD_SCALE x HUD_SCALE screen pixels
+const HUD_SCALE: usize = 2;
+
+//A custom fragment shader for the U key, colors surfaces by which way they face and keeps the light
+//they got from the Flat or Gouraud lighting
+fn normal_shader(fragment: &Fragment<Attributes>) -> LinearColor {
+	let attributes = fragment.attributes();
+	let normal = attributes.normal().normalize().unwrap_or(Point3f::new(0.0, 0.0, 0.0));
+	let facing = LinearColor::new(normal.x() * 0.5 + 0.5, normal.y() * 0.5 + 0.5, normal.z() * 0.5 + 0.5);
+
+	facing * attributes.color().luminance()
+}
+
+//A custom vertex shader for the J key, ripples the world up and down in waves running across the map
+struct WaveShader;
 
-mod triangle;
-use triangle::*;
+impl VertexShader<Attributes> for WaveShader {
+	type Vertex = MeshVertex;
 
-mod color;
-use color::Color;
+	fn shade(&self, vertex: &MeshVertex) -> (Point3f, Attributes) {
+		let position = vertex.position();
+		let height = (position.x() * 0.5).sin() * (position.z() * 0.5).cos() * 0.4;
+
+		(vertex.view() + Point3f::new(0.0, height, 0.0), vertex.attributes())
+	}
+}
 
 fn create_sdl<'a>() -> (Sdl, VideoSubsystem, render::Renderer<'a>, EventPump) {
 	let sdl_context: Sdl;
@@ -70,59 +103,93 @@ fn create_sdl<'a>() -> (Sdl, VideoSubsystem, render::Renderer<'a>, EventPump) {
 
 
 fn main() {
-	let path = Path::new("C:/Users/patri/OneDrive/Documents/Projects/mooD Engine in C/NMF-ED/bin/Debug/Maps/spaceman.nmf");
-	let mut map_file = match File::open(&path) {
-		Ok(file) => file,
+	let mut map_path = String::from("Maps/spaceman.nmf");
+	let mut obj_path: Option<String> = None;
+	let mut palette_path: Option<String> = None;
+	let mut post_path: Option<String> = None;
+	let mut headless_frames: Option<usize> = None;
+
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--export-obj" => {
+				obj_path = args.next();
+				if obj_path == None {
+					panic!("--export-obj needs an output path");
+				}
+			}
+			"--palette" => {
+				palette_path = args.next();
+				if palette_path == None {
+					panic!("--palette needs a palette file");
+				}
+			}
+			"--post" => {
+				post_path = args.next();
+				if post_path == None {
+					panic!("--post needs a post-process file");
+				}
+			}
+			"--headless" => {
+				headless_frames = args.next().and_then(|frames| frames.parse().ok());
+				if headless_frames == None {
+					panic!("--headless needs a frame count");
+				}
+			}
+			_ => {
+				map_path = arg;
+			}
+		}
+	}
+
+	let path = Path::new(&map_path);
+	let map_name = path.file_stem().map(|name| name.to_string_lossy().into_owned()).unwrap_or(map_path.clone());
+	let map = match Map::load(&path) {
+		Ok(map) => map,
 		Err(why) => panic!("failed to open {}, {}", path.display(), why.description()),
 	};
-	let mut buf_reader = BufReader::new(map_file);
-	let mut contents = String::new();
-
-	let (mut sdl_context, mut sdl_video, mut renderer, mut event_pump) = create_sdl();
 
-	let mut triangles: Vec<Triangle3D> = Vec::new();
-	let (mut x1, mut z1, mut x2, mut z2) = (0f32,0f32,0f32,0f32);
-	let mut wall = false;
-	let (mut y1, mut y2) = (1.0, -1.0);
-
-	for line in buf_reader.lines() {
-		let line = line.unwrap();
-
-		if line.contains("vertex") {
-			if !wall {
-				wall = true;
-				scan!(line.bytes() => "vertex {}, {}", x1, z1);
-			} else {
-				scan!(line.bytes() => "vertex {}, {}", x2, z2);
-
-				//1st wall Triangle2D
-				let v0 = Point3f::new(x1, y2, z1);
-				let v1 = Point3f::new(x1, y1, z1);
-				let v2 = Point3f::new(x2, y2, z2);
-				//2nd wall Triangle2D
-				let v3 = Point3f::new(x1, y1, z1);
-				let v4 = Point3f::new(x2, y2, z2);
-				let v5 = Point3f::new(x2, y1, z2);
-
-				let mut index = triangles.len();
-				triangles.push(Triangle3D::new(v0, v1, v2));
-				triangles[index].set_color(Color::new(70,120,80));
-
-				triangles.push(Triangle3D::new(v3, v4, v5));
-				//triangles[index+1].set_color(Color::new(120,80,70));
-				triangles[index+1].set_color(Color::new(70,120,80));
-
-				x1 = x2;
-				z1 = z2;
-			}
+	if let Some(obj_path) = obj_path {
+		let obj_path = Path::new(&obj_path);
+		match export_obj(&map, &obj_path) {
+			Ok(_) => println!("Exported {} to {}", path.display(), obj_path.display()),
+			Err(why) => panic!("failed to export {}, {}", obj_path.display(), why.description()),
 		}
+		return;
+	}
 
-		if line.contains("sector :") {
-			wall = false;
+	//Used by the indexed color mode and the indexed output format
+	let palette = match palette_path {
+		Some(palette_path) => match Palette::load(Path::new(&palette_path)) {
+			Ok(palette) => palette,
+			Err(why) => panic!("failed to open {}, {}", palette_path, why.description()),
+		},
+		None => Palette::rgb332(),
+	};
 
-			scan!(line.bytes() => "sector : {}, {}", y1, y2);
+	let mut scene = Scene::new(map, palette, 1920, 1080);
+	if let Some(ref post_path) = post_path {
+		match PostProcess::load(Path::new(post_path)) {
+			Ok(post_process) => scene.set_post_process(post_process),
+			Err(why) => panic!("failed to open {}, {}", post_path, why.description()),
 		}
-	}	
+	}
+	let mut cam_pos = Point3f::new(20.0,2.5,20.0);
+	let mut cam_rot = Point3f::from((0,180,0));
+
+	//Draws frames from the starting camera without a window and prints the stats for each
+	if let Some(frames) = headless_frames {
+		for frame in 0..frames {
+			scene.render(cam_pos, cam_rot);
+			println!("Frame {}\n{}\n", frame, scene.stats());
+		}
+		return;
+	}
+
+	let (mut sdl_context, mut sdl_video, mut renderer, mut event_pump) = create_sdl();
+
+	//The viewer has always shown triangle edges over the scene
+	scene.set_wireframe(WireframeMode::Overlay);
 
 	let mut counter: f32 = 0.0;
 	let mut v0 = Point3f::new( 0.0,  -0.50+(counter.sin()/2.0), 1.0);
@@ -134,11 +201,9 @@ fn main() {
 	let mut current_vert = 0;
 	let mut is_moving_vert = false;
 
-	let mut cam_pos = Point3f::new(20.0,2.5,20.0);
-	let mut cam_rot = Point3f::from((0,180,0));
 	let (mut forward, mut backward, mut left, mut right) = (false, false, false, false);
 	let speed = 0.5;
-
+	let mut hud = false;
 	let mut running = true;
     
 	let mut texture = renderer.create_texture_streaming(pixels::PixelFormatEnum::RGB24, 1920, 1080).unwrap();
@@ -191,6 +256,116 @@ fn main() {
 							keyboard::Keycode::D => {
 								right = true;
 							}
+							keyboard::Keycode::F => {
+								let filter = scene.filter().next();
+								scene.set_filter(filter);
+								println!("Texture filter: {:?}", filter);
+							}
+							keyboard::Keycode::L => {
+								let mode = scene.lighting().mode().next();
+								scene.lighting_mut().set_mode(mode);
+								println!("Shading: {:?}", mode);
+							}
+							keyboard::Keycode::O => {
+								let shadows = !scene.lighting().shadows();
+								scene.lighting_mut().set_shadows(shadows);
+								println!("Shadows: {}", shadows);
+							}
+							keyboard::Keycode::K => {
+								let diminish = match scene.lighting().diminish_distance() {
+									Some(_) => None,
+									None => Some(DIMINISH_DISTANCE),
+								};
+								scene.lighting_mut().set_diminish(diminish);
+								println!("Diminishing light: {:?}", diminish);
+							}
+							keyboard::Keycode::T => {
+								let tone_mapper = scene.tone_mapper().next();
+								scene.set_tone_mapper(tone_mapper);
+								println!("Tone mapper: {:?}", tone_mapper);
+							}
+							keyboard::Keycode::P => {
+								let (format, name) = match *scene.quantizer().format() {
+									OutputFormat::Rgb24 => (OutputFormat::Rgb565, "RGB565"),
+									OutputFormat::Rgb565 => (OutputFormat::Indexed(scene.palette().clone()), "8-bit palette"),
+									OutputFormat::Indexed(_) => (OutputFormat::Rgb24, "RGB24"),
+								};
+								scene.quantizer_mut().set_format(format);
+								println!("Output: {}", name);
+							}
+							keyboard::Keycode::C => {
+								let cull = scene.cull().next();
+								scene.set_cull(cull);
+								println!("Culling: {:?}", cull);
+							}
+							keyboard::Keycode::M => {
+								let threads = if scene.threads() == 1 { TileRenderer::<Attributes>::available_threads() } else { 1 };
+								scene.set_threads(threads);
+								println!("Render threads: {}", threads);
+							}
+							keyboard::Keycode::I => {
+								let indexed = !scene.indexed();
+								scene.set_indexed(indexed);
+								println!("Indexed color: {}", indexed);
+							}
+							keyboard::Keycode::H => {
+								hud = !hud;
+							}
+							keyboard::Keycode::V => {
+								let debug_view = scene.debug_view().next();
+								scene.set_debug_view(debug_view);
+								println!("Debug view: {:?}", debug_view);
+							}
+							keyboard::Keycode::X => {
+								let wireframe = scene.wireframe().next();
+								scene.set_wireframe(wireframe);
+								println!("Wireframe: {:?}", wireframe);
+							}
+							keyboard::Keycode::N => {
+								let antialias = scene.antialias().next();
+								scene.set_antialias(antialias);
+								println!("Anti-aliasing: {:?}", antialias);
+							}
+							keyboard::Keycode::G => {
+								let post_processing = !scene.post_processing();
+								scene.set_post_processing(post_processing);
+								println!("Post-processing: {} ({} passes)", if post_processing { "on" } else { "off" }, scene.post_process().passes().len());
+							}
+							keyboard::Keycode::R => {
+								//Picks up edits to the post-process file without restarting
+								if let Some(ref post_path) = post_path {
+									match PostProcess::load(Path::new(post_path)) {
+										Ok(post_process) => {
+											println!("Reloaded {}, {} passes", post_path, post_process.passes().len());
+											scene.set_post_process(post_process);
+										}
+										Err(why) => println!("failed to reload {}, {}", post_path, why.description()),
+									}
+								}
+							}
+							keyboard::Keycode::U => {
+								if scene.has_fragment_shader() {
+									scene.set_fragment_shader(None);
+									println!("Fragment shader: built in");
+								} else {
+									scene.set_fragment_shader(Some(Box::new(normal_shader)));
+									println!("Fragment shader: normals");
+								}
+							}
+							keyboard::Keycode::J => {
+								if scene.has_vertex_shader() {
+									scene.set_vertex_shader(None);
+									println!("Vertex shader: none");
+								} else {
+									scene.set_vertex_shader(Some(Box::new(WaveShader)));
+									println!("Vertex shader: waves");
+								}
+							}
+							keyboard::Keycode::B => {
+								let dither = scene.quantizer().dither().next();
+								scene.quantizer_mut().set_dither(dither);
+								println!("Dithering: {:?}", dither);
+							}
 
 							_ => {}
 						}
@@ -237,7 +412,6 @@ fn main() {
 
 		renderer.set_draw_color(sdl2::pixels::Color::RGB(0x00,0x00,0x00));
 		renderer.clear();
-		renderer.copy(&texture, None, None).unwrap();
 
 		/*texture.with_lock(None, |pixels: &mut [u8], pitch: usize| {
 			for y in 0..1024 {
@@ -277,35 +451,33 @@ fn main() {
 			//draw_triangle_solid(fix1, 0, 0, &mut texture);
 			//draw_triangle_solid(fix2, 0, 0, &mut texture);
 
-			for i in 0..triangles.len() {
-				let (screen_tri_1, screen_tri_2) = triangles[i].to_screen_space(cam_pos, cam_rot);
-
-				if screen_tri_1 != None {
-					let screen_tri_1 = screen_tri_1.unwrap();
-					let (fix1, fix2) = fix_triangle(&screen_tri_1);
-					draw_triangle_shaded(fix1, 0, 0, triangles[i].color(), &mut renderer);
-					draw_triangle_shaded(fix2, 0, 0, triangles[i].color(), &mut renderer);
+			scene.render(cam_pos, cam_rot);
+		}
 
-					//draw_triangle_solid(fix1, 0, 0, triangles[i].color(), &mut texture);
-					//draw_triangle_solid(fix2, 0, 0, triangles[i].color(), &mut texture);
-					draw_triangle_wireframe(screen_tri_1, 0, 0, &mut renderer);
-					//draw_triangle_wireframe(fix1, 0, 0, &mut renderer);
-					//draw_triangle_wireframe(fix2, 0, 0, &mut renderer);
-				}
-				if screen_tri_2 != None {
-					let screen_tri_2 = screen_tri_1.unwrap();
-					let (fix1, fix2) = fix_triangle(&screen_tri_2);
-					//draw_triangle_shaded(fix1, 0, 0, triangles[i].color(), &mut renderer);
-					//draw_triangle_shaded(fix2, 0, 0, triangles[i].color(), &mut renderer);
-
-					//draw_triangle_solid(fix1, 0, 0, triangles[i].color(), &mut texture);
-					//draw_triangle_solid(fix2, 0, 0, triangles[i].color(), &mut texture);
-					draw_triangle_wireframe(screen_tri_2, 0, 0, &mut renderer);
-					//draw_triangle_wireframe(fix1, 0, 0, &mut renderer);
-					//draw_triangle_wireframe(fix2, 0, 0, &mut renderer);
-				}
-			}
+		if hud {
+			let stats = *scene.stats();
+			stats.draw_hud(scene.framebuffer_mut(), HUD_SCALE);
+
+			//Where the camera is, in the bottom left corner
+			let label = format!("{}\n{:.1}, {:.1}, {:.1} facing {:.0}", map_name, cam_pos.x(), cam_pos.y(), cam_pos.z(), cam_rot.y());
+			let (width, height) = text_size(&label, HUD_SCALE);
+			let margin = 4 * HUD_SCALE;
+			let top = scene.framebuffer().height() - height - margin * 2;
+			darken_box(scene.framebuffer_mut(), 0, top, width + margin * 2, height + margin * 2);
+			draw_text_scaled(scene.framebuffer_mut(), margin, top + margin, &label, Color::new(255, 255, 255), HUD_SCALE);
 		}
+		if scene.debug_view() != DebugView::Shaded {
+			//Which debug view is on, in the top right corner
+			let label = format!("{:?}", scene.debug_view());
+			let (width, height) = text_size(&label, HUD_SCALE);
+			let margin = 4 * HUD_SCALE;
+			let left = scene.framebuffer().width() - width - margin * 2;
+			darken_box(scene.framebuffer_mut(), left, 0, width + margin * 2, height + margin * 2);
+			draw_text_scaled(scene.framebuffer_mut(), left + margin, margin, &label, Color::new(255, 255, 255), HUD_SCALE);
+		}
+		texture.update(None, scene.framebuffer().pixels(), scene.framebuffer().pitch()).unwrap();
+		renderer.copy(&texture, None, None).unwrap();
+
 		
 		//renderer.set_draw_color(sdl2::pixels::Color::RGB(0xc6,0x99,0x39));
 		//let (x,y) = tri1.get_vert(current_vert);
@@ -0,0 +1,530 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+
+use pointf::Point2f;
+use pointf::Point3f;
+use triangle::Triangle3D;
+use mesh::Mesh;
+use texture::Texture;
+use texture::WrapMode;
+use color::Color;
+use color::LinearColor;
+use lighting::Material;
+use fog::Fog;
+use color::BlendMode;
+
+//World units covered by one repeat of a texture
+const UNITS_PER_TEXTURE: f32 = 4.0;
+
+/*
+How a surface is drawn, set by texture, specular and translucent lines
+*/
+#[derive(Copy, Clone)]
+struct Style {
+	texture: Option<usize>,
+	material: Material,
+
+	//Alpha and how to blend, None for opaque surfaces
+	translucency: Option<(u8, BlendMode)>,
+}
+
+/*
+A single sector from an NMF map, along with the triangles built from it
+*/
+pub struct Sector {
+	//Maps are y-down, so the floor has the larger y
+	floor: f32,
+	ceiling: f32,
+
+	//Brightness of everything in the sector, 255 leaves colors as they are
+	light: u8,
+
+	//Outline of the sector on the x/z plane, in file order (first vertex is not repeated)
+	vertices: Vec<Point2f>,
+	neighbors: Vec<i32>,
+
+	walls: Vec<Triangle3D>,
+	floors: Vec<Triangle3D>,
+	ceilings: Vec<Triangle3D>,
+
+	//Style of the floor and ceiling, and of the walls until a line in the outline changes it
+	style: Style,
+	wall_style: Style,
+}
+
+impl Sector {
+	fn new(floor: f32, ceiling: f32, light: u8) -> Sector {
+		Sector {
+			floor: floor,
+			ceiling: ceiling,
+			light: light,
+			vertices: Vec::new(),
+			neighbors: Vec::new(),
+			walls: Vec::new(),
+			floors: Vec::new(),
+			ceilings: Vec::new(),
+			style: Style {
+				texture: None,
+				material: Material::standard(),
+				translucency: None,
+			},
+			wall_style: Style {
+				texture: None,
+				material: Material::standard(),
+				translucency: None,
+			},
+		}
+	}
+
+	//Style lines before the first vertex apply to the whole sector, later ones to the walls after them
+	fn restyle<F: Fn(&mut Style)>(&mut self, change: F) {
+		if self.vertices.is_empty() {
+			change(&mut self.style);
+		}
+		change(&mut self.wall_style);
+	}
+
+	//Adds the next outline vertex, building the wall between it and the previous one
+	fn push_vertex(&mut self, x: f32, z: f32) {
+		if let Some(&prev) = self.vertices.last() {
+			let (x1, z1) = (prev.x(), prev.y());
+			let (y1, y2) = (self.floor, self.ceiling);
+
+			//u runs along the wall and v down from the ceiling
+			let length = ((x - x1) * (x - x1) + (z - z1) * (z - z1)).sqrt() / UNITS_PER_TEXTURE;
+			let (floor_v, ceiling_v) = ((y1 - y2) / UNITS_PER_TEXTURE, 0.0);
+
+			//1st wall Triangle2D
+			let v0 = Point3f::new(x1, y2, z1);
+			let v1 = Point3f::new(x1, y1, z1);
+			let v2 = Point3f::new(x, y2, z);
+			//2nd wall Triangle2D
+			let v3 = Point3f::new(x1, y1, z1);
+			let v4 = Point3f::new(x, y1, z);
+			let v5 = Point3f::new(x, y2, z);
+
+			//Faces left of the wall, which is inside the sector for a counter-clockwise outline.
+			//finish() flips these once the whole outline is known if it turns out clockwise
+			let normal = Point3f::new(z1 - z, 0.0, x - x1).normalize().unwrap_or(Point3f::new(0.0, 0.0, 0.0));
+
+			let tri1 = self.surface([v0, v1, v2], [Point2f::new(0.0, ceiling_v), Point2f::new(0.0, floor_v), Point2f::new(length, ceiling_v)],
+									normal, self.wall_style, Color::new(70,120,80));
+			let tri2 = self.surface([v3, v4, v5], [Point2f::new(0.0, floor_v), Point2f::new(length, floor_v), Point2f::new(length, ceiling_v)],
+									normal, self.wall_style, Color::new(70,120,80));
+
+			self.walls.push(tri1);
+			self.walls.push(tri2);
+		}
+
+		self.vertices.push(Point2f::new(x, z));
+	}
+
+	//Textured surfaces are left white so the texture shows through, then everything is darkened by the sector light
+	fn surface(&self, verts: [Point3f; 3], uvs: [Point2f; 3], normal: Point3f, style: Style, color: Color) -> Triangle3D {
+		let mut tri = Triangle3D::new(verts[0], verts[1], verts[2]);
+		let color = if style.texture.is_none() { color } else { Color::new(255,255,255) };
+		tri.set_color(color.modulate(Color::new(self.light, self.light, self.light)));
+		tri.set_texture(style.texture);
+		tri.set_material(style.material);
+
+		//Light passes through translucent surfaces
+		if let Some((_, mode)) = style.translucency {
+			tri.set_blend(Some(mode));
+			tri.set_casts_shadow(false);
+		}
+
+		for (i, &uv) in uvs.iter().enumerate() {
+			let mut attributes = tri.attributes(i);
+			attributes.set_uv(uv);
+			attributes.set_normal(normal);
+			if let Some((alpha, _)) = style.translucency {
+				attributes.set_alpha(alpha);
+			}
+			tri.set_attributes(i, attributes);
+		}
+
+		tri
+	}
+
+	//Builds the floor and ceiling once the whole outline has been read
+	fn finish(&mut self) {
+		//Maps close the outline by repeating the first vertex
+		if self.vertices.len() > 1 && self.vertices.first() == self.vertices.last() {
+			self.vertices.pop();
+		}
+
+		if signed_area(&self.vertices) < 0.0 {
+			for wall in self.walls.iter_mut() {
+				for i in 0..3 {
+					let mut attributes = wall.attributes(i);
+					attributes.set_normal(-attributes.normal());
+					wall.set_attributes(i, attributes);
+				}
+			}
+		}
+
+		//Floors face up and ceilings face down, remembering that up is -y
+		for (i0, i1, i2) in triangulate(&self.vertices) {
+			let (p0, p1, p2) = (self.vertices[i0], self.vertices[i1], self.vertices[i2]);
+			let (uv0, uv1, uv2) = (p0 / UNITS_PER_TEXTURE, p1 / UNITS_PER_TEXTURE, p2 / UNITS_PER_TEXTURE);
+
+			let floor = self.surface([Point3f::new(p0.x(), self.floor, p0.y()),
+									  Point3f::new(p1.x(), self.floor, p1.y()),
+									  Point3f::new(p2.x(), self.floor, p2.y())],
+									 [uv0, uv1, uv2], Point3f::new(0.0, -1.0, 0.0), self.style, Color::new(80,70,60));
+
+			let mut ceiling = self.surface([Point3f::new(p0.x(), self.ceiling, p0.y()),
+										Point3f::new(p2.x(), self.ceiling, p2.y()),
+										Point3f::new(p1.x(), self.ceiling, p1.y())],
+									   [uv0, uv2, uv1], Point3f::new(0.0, 1.0, 0.0), self.style, Color::new(60,60,80));
+
+			//Ceilings are open to the sky as far as shadows go, otherwise every sector would be in shade
+			ceiling.set_casts_shadow(false);
+
+			self.floors.push(floor);
+			self.ceilings.push(ceiling);
+		}
+
+		//Everything winds counter-clockwise around the way it faces, into the sector, so
+		//the renderer can tell from the winding on screen which side it is looking at
+		for tri in self.walls.iter_mut().chain(self.floors.iter_mut()).chain(self.ceilings.iter_mut()) {
+			if let Some(winding_normal) = tri.normal() {
+				if winding_normal.dot(tri.attributes(0).normal()) < 0.0 {
+					tri.flip_winding();
+				}
+			}
+		}
+	}
+
+	pub fn floor(&self) -> f32 {
+		self.floor
+	}
+
+	pub fn ceiling(&self) -> f32 {
+		self.ceiling
+	}
+
+	pub fn light(&self) -> u8 {
+		self.light
+	}
+
+	pub fn vertices(&self) -> &[Point2f] {
+		&self.vertices
+	}
+
+	pub fn neighbors(&self) -> &[i32] {
+		&self.neighbors
+	}
+
+	pub fn walls(&self) -> &[Triangle3D] {
+		&self.walls
+	}
+
+	pub fn floors(&self) -> &[Triangle3D] {
+		&self.floors
+	}
+
+	pub fn ceilings(&self) -> &[Triangle3D] {
+		&self.ceilings
+	}
+}
+
+/*
+A map loaded from an NMF file
+*/
+pub struct Map {
+	sectors: Vec<Sector>,
+	textures: Vec<Texture>,
+	fog: Option<Fog>,
+}
+
+impl Map {
+	pub fn load(path: &Path) -> io::Result<Map> {
+		let map_file = File::open(path)?;
+		let buf_reader = BufReader::new(map_file);
+
+		let mut sectors: Vec<Sector> = Vec::new();
+		let mut textures: Vec<Texture> = Vec::new();
+		let mut loaded: HashMap<(String, WrapMode), usize> = HashMap::new();
+		let mut fog: Option<Fog> = None;
+
+		for line in buf_reader.lines() {
+			let line = line?;
+
+			if line.starts_with("//") {
+				continue;
+			}
+
+			//Fog covers the whole map so it can go anywhere in the file
+			if let Some(fog_line) = line.strip_prefix("fog ") {
+				fog = Fog::parse(fog_line);
+				if fog.is_none() {
+					return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad fog line: {}", line)));
+				}
+				continue;
+			}
+
+			if line.contains("sector :") {
+				if let Some(sector) = sectors.last_mut() {
+					sector.finish();
+				}
+
+				let (floor, ceiling): (f32, f32);
+				scan!(line.bytes() => "sector : {}, {}", floor, ceiling);
+
+				//sector : <floor>, <ceiling> [light <0-255>], older maps have no light and are fully bright
+				let mut words = line.split_whitespace().skip_while(|&w| w != "light").skip(1);
+				let light = words.next().and_then(|w| w.parse::<u8>().ok()).unwrap_or(255);
+
+				sectors.push(Sector::new(floor, ceiling, light));
+				continue;
+			}
+
+			let sector = match sectors.last_mut() {
+				Some(sector) => sector,
+				None => continue,
+			};
+
+			//texture <file> [repeat|clamp], the file is relative to the map
+			if line.starts_with("texture ") {
+				let mut words = line.split_whitespace().skip(1);
+				let name = words.next().unwrap_or("").to_string();
+				let wrap = match words.next() {
+					Some("clamp") => WrapMode::Clamp,
+					_ => WrapMode::Repeat,
+				};
+
+				let key = (name, wrap);
+				let index = match loaded.get(&key) {
+					Some(&index) => index,
+					None => {
+						let texture_path = path.parent().unwrap_or(Path::new("")).join(&key.0);
+						let mut texture = Texture::load(&texture_path).map_err(|e| {
+							io::Error::new(e.kind(), format!("{}: {}", texture_path.display(), e))
+						})?;
+						texture.set_wrap(wrap);
+
+						textures.push(texture);
+						textures.len() - 1
+					}
+				};
+				loaded.insert(key, index);
+
+				sector.restyle(|style| style.texture = Some(index));
+			} else if line.starts_with("specular ") {
+				//specular <exponent> [<level>], level is 0-255 and scoped like texture lines
+				let mut words = line.split_whitespace().skip(1);
+				let shininess = words.next().and_then(|w| w.parse::<f32>().ok()).unwrap_or(Material::standard().shininess());
+				let level = words.next().and_then(|w| w.parse::<u8>().ok()).unwrap_or(255);
+
+				let material = Material::new(LinearColor::from_srgb(Color::new(level, level, level)), shininess);
+				sector.restyle(|style| style.material = material);
+			} else if line.starts_with("translucent ") {
+				//translucent <alpha> [alpha|additive|multiply], alpha is 0-255 and scoped like texture lines
+				let mut words = line.split_whitespace().skip(1);
+				let alpha = words.next().and_then(|w| w.parse::<u8>().ok()).unwrap_or(128);
+				let mode = match words.next() {
+					Some("additive") => BlendMode::Additive,
+					Some("multiply") => BlendMode::Multiply,
+					_ => BlendMode::Alpha,
+				};
+
+				sector.restyle(|style| style.translucency = Some((alpha, mode)));
+			} else if line.trim() == "opaque" {
+				sector.restyle(|style| style.translucency = None);
+			} else if line.contains("vertex") {
+				let (x, z): (f32, f32);
+				scan!(line.bytes() => "vertex {}, {}", x, z);
+
+				sector.push_vertex(x, z);
+			} else if line.contains("neighbor") {
+				let neighbor: i32;
+				scan!(line.bytes() => "neighbor {}", neighbor);
+
+				sector.neighbors.push(neighbor);
+			}
+		}
+
+		if let Some(sector) = sectors.last_mut() {
+			sector.finish();
+		}
+
+		Ok(Map {
+			sectors: sectors,
+			textures: textures,
+			fog: fog,
+		})
+	}
+
+	pub fn sectors(&self) -> &[Sector] {
+		&self.sectors
+	}
+
+	pub fn textures(&self) -> &[Texture] {
+		&self.textures
+	}
+
+	pub fn fog(&self) -> Option<Fog> {
+		self.fog
+	}
+
+	//Builds an indexed mesh of every wall, floor and ceiling in the map
+	pub fn mesh(&self) -> Mesh {
+		let mut mesh = Mesh::new();
+		for (index, sector) in self.sectors.iter().enumerate() {
+			for tri in sector.walls.iter().chain(sector.floors.iter()).chain(sector.ceilings.iter()) {
+				mesh.push_triangle(tri, index);
+			}
+		}
+
+		mesh
+	}
+}
+
+//Twice the signed area of the polygon, positive when counter-clockwise
+fn signed_area(polygon: &[Point2f]) -> f32 {
+	let mut area = 0.0;
+	for i in 0..polygon.len() {
+		let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+		area += a.x() * b.y() - b.x() * a.y();
+	}
+
+	area
+}
+
+fn cross(a: Point2f, b: Point2f, c: Point2f) -> f32 {
+	(b.x() - a.x()) * (c.y() - a.y()) - (b.y() - a.y()) * (c.x() - a.x())
+}
+
+fn point_in_triangle(p: Point2f, a: Point2f, b: Point2f, c: Point2f) -> bool {
+	let d0 = cross(a, b, p);
+	let d1 = cross(b, c, p);
+	let d2 = cross(c, a, p);
+
+	let has_neg = d0 < 0.0 || d1 < 0.0 || d2 < 0.0;
+	let has_pos = d0 > 0.0 || d1 > 0.0 || d2 > 0.0;
+
+	!(has_neg && has_pos)
+}
+
+//Ear clipping triangulation of a simple polygon, returns indices into the polygon
+fn triangulate(polygon: &[Point2f]) -> Vec<(usize, usize, usize)> {
+	let mut triangles = Vec::new();
+	if polygon.len() < 3 {
+		return triangles;
+	}
+
+	let winding = if signed_area(polygon) < 0.0 { -1.0 } else { 1.0 };
+	let mut remaining: Vec<usize> = (0..polygon.len()).collect();
+
+	while remaining.len() > 3 {
+		let n = remaining.len();
+		let mut ear = None;
+
+		for i in 0..n {
+			let (ia, ib, ic) = (remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]);
+			let (a, b, c) = (polygon[ia], polygon[ib], polygon[ic]);
+
+			//Collinear vertices add nothing, drop them without emitting a triangle
+			let turn = cross(a, b, c) * winding;
+			if turn == 0.0 {
+				ear = Some(i);
+				break;
+			}
+			if turn < 0.0 {
+				continue;
+			}
+
+			let blocked = remaining.iter()
+				.filter(|&&j| j != ia && j != ib && j != ic)
+				.any(|&j| polygon[j] != a && polygon[j] != b && polygon[j] != c && point_in_triangle(polygon[j], a, b, c));
+
+			if !blocked {
+				ear = Some(i);
+				break;
+			}
+		}
+
+		//Degenerate outlines may not have a proper ear, clip one anyway so we always finish
+		let i = ear.unwrap_or(0);
+		let (ia, ib, ic) = (remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]);
+		if cross(polygon[ia], polygon[ib], polygon[ic]) != 0.0 {
+			triangles.push((ia, ib, ic));
+		}
+		remaining.remove(i);
+	}
+
+	if cross(polygon[remaining[0]], polygon[remaining[1]], polygon[remaining[2]]) != 0.0 {
+		triangles.push((remaining[0], remaining[1], remaining[2]));
+	}
+
+	triangles
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn polygon(points: &[(f32, f32)]) -> Vec<Point2f> {
+		points.iter().map(|&p| Point2f::from(p)).collect()
+	}
+
+	//Total area of the triangles, each counted positive if it winds the same way as the polygon
+	fn covered_area(polygon: &[Point2f], triangles: &[(usize, usize, usize)]) -> f32 {
+		let winding = signed_area(polygon).signum();
+		triangles.iter().map(|&(a, b, c)| cross(polygon[a], polygon[b], polygon[c]) * winding).sum()
+	}
+
+	#[test]
+	fn triangulate_square() {
+		let square = polygon(&[(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)]);
+		let triangles = triangulate(&square);
+
+		assert_eq!(triangles.len(), 2);
+		assert_eq!(covered_area(&square, &triangles), signed_area(&square).abs());
+	}
+
+	#[test]
+	fn triangulate_concave_either_winding() {
+		let l_shape = polygon(&[(0.0, 0.0), (4.0, 0.0), (4.0, 1.0), (1.0, 1.0), (1.0, 4.0), (0.0, 4.0)]);
+		let mut reversed = l_shape.clone();
+		reversed.reverse();
+
+		for outline in [l_shape, reversed].iter() {
+			let triangles = triangulate(outline);
+			assert_eq!(triangles.len(), outline.len() - 2);
+
+			//No triangle winds backwards, so none covers the notch
+			let winding = signed_area(outline).signum();
+			assert!(triangles.iter().all(|&(a, b, c)| cross(outline[a], outline[b], outline[c]) * winding > 0.0));
+			assert_eq!(covered_area(outline, &triangles), signed_area(outline).abs());
+		}
+	}
+
+	#[test]
+	fn triangulate_skips_collinear_and_degenerate() {
+		let with_midpoint = polygon(&[(0.0, 0.0), (2.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)]);
+		let triangles = triangulate(&with_midpoint);
+		assert!(triangles.iter().all(|&(a, b, c)| cross(with_midpoint[a], with_midpoint[b], with_midpoint[c]) != 0.0));
+		assert_eq!(covered_area(&with_midpoint, &triangles), signed_area(&with_midpoint).abs());
+
+		assert!(triangulate(&polygon(&[(0.0, 0.0), (1.0, 1.0)])).is_empty());
+		assert!(triangulate(&polygon(&[(0.0, 0.0), (1.0, 1.0), (2.0, 2.0)])).is_empty());
+	}
+
+	#[test]
+	fn wall_triangles_wind_the_same_way() {
+		let map = Map::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("Maps/funtime.nmf")).unwrap();
+
+		for sector in map.sectors() {
+			for quad in sector.walls().chunks(2) {
+				let (first, second) = (quad[0].normal().unwrap(), quad[1].normal().unwrap());
+				assert!(first.dot(second) > 0.0);
+			}
+		}
+	}
+}
@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+
+use pointf::Point3f;
+use triangle::Triangle3D;
+use raster::ScreenVertex;
+use raster::ScreenTriangle;
+use raster::NEAR_PLANE;
+use raster::clip_triangle;
+use vertex::Attributes;
+use color::LinearColor;
+use color::BlendMode;
+use lighting::Lighting;
+use lighting::ShadeMode;
+use lighting::Material;
+use shader::VertexShader;
+
+/*
+A corner of a mesh triangle the way a vertex shader sees it: where it is in the world and in camera
+view space, and the attributes the built in shading would draw it with
+*/
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MeshVertex {
+	position: Point3f,
+	view: Point3f,
+	attributes: Attributes,
+}
+
+impl MeshVertex {
+	pub fn new(position: Point3f, view: Point3f, attributes: Attributes) -> MeshVertex {
+		MeshVertex {
+			position: position,
+			view: view,
+			attributes: attributes,
+		}
+	}
+
+	pub fn position(&self) -> Point3f {
+		self.position
+	}
+
+	pub fn view(&self) -> Point3f {
+		self.view
+	}
+
+	pub fn attributes(&self) -> Attributes {
+		self.attributes
+	}
+}
+
+/*
+An indexed triangle mesh, each vertex is stored once and shared by every triangle that uses it
+*/
+pub struct Mesh {
+	positions: Vec<Point3f>,
+	attributes: Vec<Attributes>,
+	indices: Vec<usize>,
+
+	//Texture index into the map, material, face normal, whether it blocks light, how it blends and
+	//the map sector it came from for each triangle
+	textures: Vec<Option<usize>>,
+	materials: Vec<Material>,
+	normals: Vec<Point3f>,
+	shadow_casters: Vec<bool>,
+	blends: Vec<Option<BlendMode>>,
+	sectors: Vec<usize>,
+
+	//Looks up existing vertices by position so shared ones are only stored once
+	lookup: HashMap<[u32; 3], Vec<usize>>,
+
+	//Per-frame cache, filled by transform()
+	view: Vec<Point3f>,
+	projected: Vec<Point3f>,
+	camera: Option<(Point3f, Point3f)>,
+
+	//Lit colors filled by light(), per triangle when flat shading and per vertex for Gouraud.
+	//Phong shading lights each pixel instead so nothing is stored. The shadowed light is kept apart
+	//from the others for the shadow to be looked up per pixel
+	lit: Vec<(LinearColor, LinearColor)>,
+	shade_mode: Option<ShadeMode>,
+
+	//Vertex shader output filled by shade_vertices(), one per vertex, empty to draw the mesh as it is
+	shaded: Vec<(Point3f, Attributes)>,
+	shaded_projected: Vec<Point3f>,
+}
+
+impl Mesh {
+	pub fn new() -> Mesh {
+		Mesh {
+			positions: Vec::new(),
+			attributes: Vec::new(),
+			indices: Vec::new(),
+			textures: Vec::new(),
+			materials: Vec::new(),
+			normals: Vec::new(),
+			shadow_casters: Vec::new(),
+			blends: Vec::new(),
+			sectors: Vec::new(),
+			lookup: HashMap::new(),
+			view: Vec::new(),
+			projected: Vec::new(),
+			camera: None,
+			lit: Vec::new(),
+			shade_mode: None,
+			shaded: Vec::new(),
+			shaded_projected: Vec::new(),
+		}
+	}
+
+	//Adds a vertex, reusing an identical one if it already exists
+	pub fn push_vertex(&mut self, position: Point3f, attributes: Attributes) -> usize {
+		let key = [position.x().to_bits(), position.y().to_bits(), position.z().to_bits()];
+
+		let candidates = self.lookup.entry(key).or_default();
+		for &index in candidates.iter() {
+			if self.attributes[index] == attributes {
+				return index;
+			}
+		}
+
+		let index = self.positions.len();
+		self.positions.push(position);
+		self.attributes.push(attributes);
+		candidates.push(index);
+		self.camera = None;
+		self.clear_shaded();
+
+		index
+	}
+
+	pub fn push_triangle(&mut self, tri: &Triangle3D, sector: usize) {
+		let i0 = self.push_vertex(tri.v0(), tri.attributes(0));
+		let i1 = self.push_vertex(tri.v1(), tri.attributes(1));
+		let i2 = self.push_vertex(tri.v2(), tri.attributes(2));
+
+		self.indices.push(i0);
+		self.indices.push(i1);
+		self.indices.push(i2);
+		self.textures.push(tri.texture());
+		self.materials.push(tri.material());
+		self.shadow_casters.push(tri.casts_shadow());
+		self.blends.push(tri.blend());
+		self.sectors.push(sector);
+
+		//Keep the face normal on the same side as the vertex normals, whatever the winding
+		let normal = tri.normal().unwrap_or(Point3f::new(0.0, 0.0, 0.0));
+		let facing = tri.attributes(0).normal();
+		self.normals.push(if normal.dot(facing) < 0.0 { -normal } else { normal });
+		self.shade_mode = None;
+	}
+
+	//Transforms and projects every vertex once, skipped if the camera hasn't moved since the last call
+	pub fn transform(&mut self, cam_pos: Point3f, cam_rot: Point3f) {
+		if self.camera == Some((cam_pos, cam_rot)) {
+			return;
+		}
+
+		self.view.clear();
+		self.projected.clear();
+
+		for position in &self.positions {
+			let view = position.to_camera_view(cam_pos, cam_rot);
+
+			self.view.push(view);
+			self.projected.push(view.perspective_project());
+		}
+
+		self.camera = Some((cam_pos, cam_rot));
+	}
+
+	//Lights the mesh for the next frame, lights may have moved so this is redone every call
+	pub fn light(&mut self, lighting: &Lighting, eye: Point3f) {
+		self.lit.clear();
+
+		match lighting.mode() {
+			ShadeMode::Flat => {
+				for tri in 0..self.triangle_count() {
+					let (i0, i1, i2) = self.triangle(tri);
+					let centroid = (self.positions[i0] + self.positions[i1] + self.positions[i2]) / 3.0;
+
+					self.lit.push(lighting.illuminate_unshadowed(centroid, self.normals[tri], eye, self.attributes[i0].color(), self.materials[tri]));
+				}
+			}
+			ShadeMode::Gouraud => {
+				//Vertices shared between triangles of different materials take the first one's
+				let mut materials = vec![Material::standard(); self.positions.len()];
+				for (i, &index) in self.indices.iter().enumerate().rev() {
+					materials[index] = self.materials[i / 3];
+				}
+
+				for (i, (position, attributes)) in self.positions.iter().zip(self.attributes.iter()).enumerate() {
+					self.lit.push(lighting.illuminate_unshadowed(*position, attributes.normal(), eye, attributes.color(), materials[i]));
+				}
+			}
+			ShadeMode::Phong => {}
+		}
+
+		self.shade_mode = Some(lighting.mode());
+	}
+
+	//Runs the vertex shader once on every vertex, however many triangles share it, and keeps the
+	//results for to_screen_space(). Needs transform() and light() to have been called this frame
+	pub fn shade_vertices<VS>(&mut self, vertex_shader: &VS)
+		where VS: VertexShader<Attributes, Vertex = MeshVertex> + ?Sized
+	{
+		self.shaded.clear();
+		self.shaded_projected.clear();
+
+		for index in 0..self.positions.len() {
+			let (view, mut attributes) = vertex_shader.shade(&self.vertex(index));
+			attributes.set_depth(-view.z());
+
+			self.shaded.push((view, attributes));
+			self.shaded_projected.push(view.perspective_project());
+		}
+	}
+
+	//Goes back to drawing the vertices from transform() as they are
+	pub fn clear_shaded(&mut self) {
+		self.shaded.clear();
+		self.shaded_projected.clear();
+	}
+
+	//Uses the cached vertices from transform(), or shade_vertices() if it ran, only triangles crossing
+	//the near plane need more work. Flat shading lights whole triangles so its color replaces the one
+	//a vertex shader gave the corners
+	pub fn to_screen_space(&self, tri: usize) -> (Option<ScreenTriangle<Attributes>>, Option<ScreenTriangle<Attributes>>) {
+		let (i0, i1, i2) = self.triangle(tri);
+
+		let (c0, c1, c2) = (self.corner(tri, i0), self.corner(tri, i1), self.corner(tri, i2));
+
+		if c0.0.z() <= -NEAR_PLANE && c1.0.z() <= -NEAR_PLANE && c2.0.z() <= -NEAR_PLANE {
+			return (Some([ScreenVertex::new(c0.1, c0.2),
+						  ScreenVertex::new(c1.1, c1.2),
+						  ScreenVertex::new(c2.1, c2.2)]), None);
+		}
+
+		clip_triangle([(c0.0, c0.2), (c1.0, c1.2), (c2.0, c2.2)])
+	}
+
+	//A vertex as of the last transform() and light(), the way a vertex shader gets it
+	pub fn vertex(&self, index: usize) -> MeshVertex {
+		MeshVertex::new(self.positions[index], self.view[index], self.view_attributes(index))
+	}
+
+	//A corner of triangle tri in view space, projected, and its attributes
+	fn corner(&self, tri: usize, index: usize) -> (Point3f, Point3f, Attributes) {
+		let (view, projected, mut attributes) = if self.shaded.is_empty() {
+			(self.view[index], self.projected[index], self.view_attributes(index))
+		} else {
+			(self.shaded[index].0, self.shaded_projected[index], self.shaded[index].1)
+		};
+
+		if let Some(ShadeMode::Flat) = self.shade_mode {
+			let (color, shadowed) = self.lit[tri];
+			attributes.set_color(color);
+			attributes.set_shadowed(shadowed);
+		}
+
+		(view, projected, attributes)
+	}
+
+	//The vertex attributes with the depth and Gouraud lighting filled in for the current frame
+	fn view_attributes(&self, index: usize) -> Attributes {
+		let mut attributes = self.attributes[index];
+		attributes.set_depth(-self.view[index].z());
+
+		if let Some((eye, _)) = self.camera {
+			attributes.set_view(eye - self.positions[index]);
+		}
+
+		if let Some(ShadeMode::Gouraud) = self.shade_mode {
+			let (color, shadowed) = self.lit[index];
+			attributes.set_color(color);
+			attributes.set_shadowed(shadowed);
+		}
+
+		attributes
+	}
+
+	pub fn triangle(&self, tri: usize) -> (usize, usize, usize) {
+		(self.indices[tri * 3], self.indices[tri * 3 + 1], self.indices[tri * 3 + 2])
+	}
+
+	pub fn position(&self, index: usize) -> Point3f {
+		self.positions[index]
+	}
+
+	//The triangle's color before lighting, white modulated by the sector light for textured triangles
+	pub fn base_color(&self, tri: usize) -> LinearColor {
+		self.attributes[self.indices[tri * 3]].color()
+	}
+
+	pub fn texture(&self, tri: usize) -> Option<usize> {
+		self.textures[tri]
+	}
+
+	pub fn material(&self, tri: usize) -> Material {
+		self.materials[tri]
+	}
+
+	pub fn casts_shadow(&self, tri: usize) -> bool {
+		self.shadow_casters[tri]
+	}
+
+	pub fn blend(&self, tri: usize) -> Option<BlendMode> {
+		self.blends[tri]
+	}
+
+	pub fn sector(&self, tri: usize) -> usize {
+		self.sectors[tri]
+	}
+
+	pub fn vertex_count(&self) -> usize {
+		self.positions.len()
+	}
+
+	pub fn triangle_count(&self) -> usize {
+		self.indices.len() / 3
+	}
+}
+
+impl Default for Mesh {
+	fn default() -> Mesh {
+		Mesh::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::cell::Cell;
+	use color::Color;
+
+	//Lifts every vertex by one, counting how many it has shaded
+	struct Lift {
+		shaded: Cell<usize>,
+	}
+
+	impl VertexShader<Attributes> for Lift {
+		type Vertex = MeshVertex;
+
+		fn shade(&self, vertex: &MeshVertex) -> (Point3f, Attributes) {
+			self.shaded.set(self.shaded.get() + 1);
+			(vertex.view() + Point3f::new(0.0, 1.0, 0.0), vertex.attributes())
+		}
+	}
+
+	//A square in the y = 0 plane split on its diagonal, the two triangles share two corners
+	fn square() -> Mesh {
+		let corners = [Point3f::new(0.0, 0.0, 0.0), Point3f::new(1.0, 0.0, 0.0), Point3f::new(1.0, 0.0, 1.0), Point3f::new(0.0, 0.0, 1.0)];
+		let mut mesh = Mesh::new();
+		mesh.push_triangle(&Triangle3D::new(corners[0], corners[1], corners[2]), 0);
+		mesh.push_triangle(&Triangle3D::new(corners[0], corners[2], corners[3]), 0);
+		mesh
+	}
+
+	#[test]
+	fn shared_vertices_are_stored_once() {
+		let mesh = square();
+		assert_eq!(mesh.triangle_count(), 2);
+		assert_eq!(mesh.vertex_count(), 4);
+
+		let (a0, _, a2) = mesh.triangle(0);
+		let (b0, b1, _) = mesh.triangle(1);
+		assert_eq!((a0, a2), (b0, b1));
+	}
+
+	#[test]
+	fn vertices_with_different_attributes_are_kept_apart() {
+		let mut mesh = Mesh::new();
+		let position = Point3f::new(1.0, 2.0, 3.0);
+		let red = mesh.push_vertex(position, Attributes::new(Color::new(255, 0, 0)));
+		let blue = mesh.push_vertex(position, Attributes::new(Color::new(0, 0, 255)));
+
+		assert!(red != blue);
+		assert_eq!(mesh.push_vertex(position, Attributes::new(Color::new(255, 0, 0))), red);
+	}
+
+	#[test]
+	fn transform_runs_once_per_vertex_and_only_when_the_camera_moves() {
+		let mut mesh = square();
+		let (cam_pos, cam_rot) = (Point3f::new(0.5, -2.0, -3.0), Point3f::new(0.0, 180.0, 0.0));
+
+		mesh.transform(cam_pos, cam_rot);
+		assert_eq!(mesh.view.len(), mesh.vertex_count());
+		assert_eq!(mesh.projected.len(), mesh.vertex_count());
+		assert_eq!(mesh.view[0], mesh.position(0).to_camera_view(cam_pos, cam_rot));
+
+		//Marks the cache so it shows whether the next call redid it
+		let marker = Point3f::new(1234.0, 0.0, 0.0);
+		mesh.view[0] = marker;
+		mesh.transform(cam_pos, cam_rot);
+		assert_eq!(mesh.view[0], marker);
+
+		mesh.transform(cam_pos + Point3f::new(0.0, 0.0, 1.0), cam_rot);
+		assert!(mesh.view[0] != marker);
+		assert_eq!(mesh.view.len(), mesh.vertex_count());
+
+		//New vertices mean the cache is out of date even from the same camera
+		let moved_camera = cam_pos + Point3f::new(0.0, 0.0, 1.0);
+		mesh.view[0] = marker;
+		mesh.push_vertex(Point3f::new(5.0, 0.0, 5.0), Attributes::new(Color::new(0, 0, 0)));
+		mesh.transform(moved_camera, cam_rot);
+		assert!(mesh.view[0] != marker);
+		assert_eq!(mesh.view.len(), 5);
+	}
+
+	#[test]
+	fn vertex_shader_runs_once_per_vertex_and_triangles_use_its_output() {
+		let mut mesh = square();
+		mesh.transform(Point3f::new(0.5, -2.0, -3.0), Point3f::new(0.0, 180.0, 0.0));
+		let (plain, _) = mesh.to_screen_space(1);
+		let plain = plain.unwrap();
+
+		let vertex_shader = Lift { shaded: Cell::new(0) };
+		mesh.shade_vertices(&vertex_shader);
+		//Two triangles, six corners, but only four vertices
+		assert_eq!(vertex_shader.shaded.get(), 4);
+
+		let (i0, i1, i2) = mesh.triangle(1);
+		let (shaded, _) = mesh.to_screen_space(1);
+		for (vertex, &index) in shaded.unwrap().iter().zip([i0, i1, i2].iter()) {
+			assert_eq!(vertex.position(), (mesh.view[index] + Point3f::new(0.0, 1.0, 0.0)).perspective_project());
+		}
+
+		mesh.clear_shaded();
+		let (cleared, _) = mesh.to_screen_space(1);
+		for (a, b) in cleared.unwrap().iter().zip(plain.iter()) {
+			assert_eq!(a.position(), b.position());
+		}
+	}
+}
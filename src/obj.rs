@@ -0,0 +1,87 @@
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+
+use map::Map;
+use triangle::Triangle3D;
+
+//Writes every wall, floor and ceiling triangle of the map as a Wavefront OBJ file, see write_obj()
+pub fn export_obj(map: &Map, path: &Path) -> io::Result<()> {
+	let mut out = BufWriter::new(File::create(path)?);
+	write_obj(map, &mut out)?;
+
+	out.flush()
+}
+
+//Writes the map as a Wavefront OBJ, one group per sector
+pub fn write_obj<W: Write>(map: &Map, out: &mut W) -> io::Result<()> {
+	writeln!(out, "# {} sectors", map.sectors().len())?;
+
+	//OBJ indices are 1-based and global to the file
+	let mut next_index = 1;
+
+	for (i, sector) in map.sectors().iter().enumerate() {
+		writeln!(out, "g sector_{}", i)?;
+
+		let surfaces = sector.walls().iter()
+			.chain(sector.floors().iter())
+			.chain(sector.ceilings().iter());
+
+		let mut faces: Vec<usize> = Vec::new();
+		for tri in surfaces {
+			write_vertices(out, tri)?;
+			faces.push(next_index);
+			next_index += 3;
+		}
+
+		for index in faces {
+			writeln!(out, "f {} {} {}", index, index + 1, index + 2)?;
+		}
+	}
+
+	Ok(())
+}
+
+fn write_vertices<W: Write>(out: &mut W, tri: &Triangle3D) -> io::Result<()> {
+	for v in &[tri.v0(), tri.v1(), tri.v2()] {
+		writeln!(out, "v {} {} {}", v.x(), v.y(), v.z())?;
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use pointf::Point3f;
+
+	#[test]
+	fn writes_every_triangle_grouped_by_sector() {
+		let map = Map::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("Maps/funtime.nmf")).unwrap();
+		let mut out = Vec::new();
+		write_obj(&map, &mut out).unwrap();
+		let text = String::from_utf8(out).unwrap();
+
+		let triangles: usize = map.sectors().iter().map(|s| s.walls().len() + s.floors().len() + s.ceilings().len()).sum();
+		let vertices: Vec<Point3f> = text.lines().filter(|l| l.starts_with("v ")).map(|l| {
+			let v: Vec<f32> = l[2..].split_whitespace().map(|n| n.parse().unwrap()).collect();
+			Point3f::new(v[0], v[1], v[2])
+		}).collect();
+		let faces: Vec<Vec<usize>> = text.lines().filter(|l| l.starts_with("f ")).map(|l| {
+			l[2..].split_whitespace().map(|n| n.parse().unwrap()).collect()
+		}).collect();
+
+		assert_eq!(text.lines().filter(|l| l.starts_with("g sector_")).count(), map.sectors().len());
+		assert_eq!(vertices.len(), triangles * 3);
+		assert_eq!(faces.len(), triangles);
+
+		//Indices are 1-based and faces come out in the same order as the sector's triangles
+		let first = map.sectors()[0].walls()[0];
+		assert_eq!(faces[0], vec![1, 2, 3]);
+		assert_eq!(&vertices[0..3], &[first.v0(), first.v1(), first.v2()]);
+		assert!(faces.iter().flat_map(|f| f.iter()).all(|&i| i >= 1 && i <= vertices.len()));
+	}
+}
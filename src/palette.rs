@@ -0,0 +1,240 @@
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use color::Color;
+use color::LinearColor;
+use texture::invalid_data;
+
+//Bits per channel kept by the palette lookup cache, 5 keeps it at 32K entries
+const CACHE_BITS: usize = 5;
+
+/*
+A fixed set of colors to quantize to. Finding the nearest entry is too slow to do per pixel
+so the answer is cached for every color at CACHE_BITS per channel
+*/
+#[derive(Clone)]
+pub struct Palette {
+	colors: Vec<Color>,
+	cache: Vec<u8>,
+}
+
+impl Palette {
+	//Up to 256 colors, so an index fits in a byte
+	pub fn new(colors: Vec<Color>) -> Palette {
+		assert!(!colors.is_empty() && colors.len() <= 256);
+
+		let levels = 1 << CACHE_BITS;
+		let mut palette = Palette {
+			colors: colors,
+			cache: Vec::with_capacity(levels * levels * levels),
+		};
+
+		//Each cache entry covers a range of colors, the one in the middle stands for it
+		let expand = |c: usize| ((c << (8 - CACHE_BITS)) + (1 << (7 - CACHE_BITS))) as u8;
+		for r in 0..levels {
+			for g in 0..levels {
+				for b in 0..levels {
+					let nearest = palette.search(Color::new(expand(r), expand(g), expand(b)));
+					palette.cache.push(nearest as u8);
+				}
+			}
+		}
+
+		palette
+	}
+
+	//3 bits of red and green and 2 of blue, the usual fixed 8-bit palette
+	pub fn rgb332() -> Palette {
+		let mut colors = Vec::with_capacity(256);
+		for i in 0..256usize {
+			let (r, g, b) = (i >> 5, (i >> 2) & 7, i & 3);
+			colors.push(Color::new((r * 255 / 7) as u8, (g * 255 / 7) as u8, (b * 255 / 3) as u8));
+		}
+
+		Palette::new(colors)
+	}
+
+	fn search(&self, color: Color) -> usize {
+		let distance = |c: &Color| {
+			let (dr, dg, db) = (c.r() as i32 - color.r() as i32, c.g() as i32 - color.g() as i32, c.b() as i32 - color.b() as i32);
+			dr * dr + dg * dg + db * db
+		};
+
+		let mut best = 0;
+		for (i, c) in self.colors.iter().enumerate() {
+			if distance(c) < distance(&self.colors[best]) {
+				best = i;
+			}
+		}
+		best
+	}
+
+	//Index of the closest palette entry
+	pub fn nearest(&self, color: Color) -> u8 {
+		let shift = 8 - CACHE_BITS;
+		let (r, g, b) = ((color.r() >> shift) as usize, (color.g() >> shift) as usize, (color.b() >> shift) as usize);
+
+		self.cache[(r << (CACHE_BITS * 2)) | (g << CACHE_BITS) | b]
+	}
+
+	//Loads a JASC-PAL text palette, or a raw file of RGB triples like a .pal or Doom's PLAYPAL.
+	//Raw files may hold several palettes one after another, only the first is used
+	pub fn load(path: &Path) -> io::Result<Palette> {
+		let mut data = Vec::new();
+		File::open(path)?.read_to_end(&mut data)?;
+
+		Ok(Palette::new(decode_palette(&data)?))
+	}
+
+	pub fn color(&self, index: u8) -> Color {
+		self.colors[index as usize]
+	}
+
+	pub fn len(&self) -> usize {
+		self.colors.len()
+	}
+
+	//Always false, Palette::new() needs at least one color
+	pub fn is_empty(&self) -> bool {
+		self.colors.is_empty()
+	}
+}
+
+/*
+Lighting for indexed color, the way classic sector renderers did it. Each light level has a row
+that maps every palette index to the index that best matches it at that brightness, so shading a
+pixel is a single lookup and never leaves the palette
+*/
+pub struct Colormap {
+	levels: usize,
+	table: Vec<u8>,
+}
+
+impl Colormap {
+	//levels rows from black up to full brightness, darkening happens in linear light
+	pub fn new(palette: &Palette, levels: usize) -> Colormap {
+		assert!(levels >= 2);
+
+		let mut table = Vec::with_capacity(levels * 256);
+		for level in 0..levels {
+			let brightness = level as f32 / (levels - 1) as f32;
+
+			for index in 0..256 {
+				let index = (index % palette.len()) as u8;
+				let lit = LinearColor::from_srgb(palette.color(index)) * brightness;
+				table.push(palette.nearest(lit.to_srgb()));
+			}
+		}
+
+		Colormap {
+			levels: levels,
+			table: table,
+		}
+	}
+
+	//The palette index for index seen at light, 0 is black and 1 full brightness
+	pub fn light(&self, index: u8, light: f32) -> u8 {
+		let level = (light.clamp(0.0, 1.0) * (self.levels - 1) as f32 + 0.5) as usize;
+		self.table[level * 256 + index as usize]
+	}
+
+	pub fn levels(&self) -> usize {
+		self.levels
+	}
+}
+
+//The colors of a palette file in either format Palette::load() takes
+fn decode_palette(data: &[u8]) -> io::Result<Vec<Color>> {
+	if data.starts_with(b"JASC-PAL") {
+		return parse_jasc(&String::from_utf8_lossy(data));
+	}
+
+	if data.len() < 3 || !data.len().is_multiple_of(3) {
+		return Err(invalid_data("not a palette"));
+	}
+
+	Ok(data.chunks(3).take(256).map(|c| Color::new(c[0], c[1], c[2])).collect())
+}
+
+//JASC-PAL is a header line, a version line, the color count then one "r g b" line per color
+fn parse_jasc(text: &str) -> io::Result<Vec<Color>> {
+	let mut lines = text.lines().map(|l| l.trim()).filter(|l| !l.is_empty()).skip(2);
+	let count = lines.next()
+		.and_then(|l| l.parse::<usize>().ok())
+		.ok_or(invalid_data("missing JASC-PAL color count"))?;
+
+	if count == 0 || count > 256 {
+		return Err(invalid_data("JASC-PAL palettes need 1 to 256 colors"));
+	}
+
+	let mut colors = Vec::with_capacity(count);
+	for line in lines.take(count) {
+		let channels: Vec<u8> = line.split_whitespace()
+			.map(|w| w.parse::<u8>().ok())
+			.collect::<Option<Vec<u8>>>()
+			.ok_or(invalid_data(&format!("bad JASC-PAL color: {}", line)))?;
+
+		if channels.len() != 3 {
+			return Err(invalid_data(&format!("bad JASC-PAL color: {}", line)));
+		}
+		colors.push(Color::new(channels[0], channels[1], channels[2]));
+	}
+
+	if colors.len() != count {
+		return Err(invalid_data("JASC-PAL palette is missing colors"));
+	}
+	Ok(colors)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_jasc() {
+		let colors = decode_palette(b"JASC-PAL\r\n0100\r\n3\r\n255 0 0\r\n0 128 0\r\n  0 0 255  \r\n").unwrap();
+
+		assert_eq!(colors, vec![Color::new(255, 0, 0), Color::new(0, 128, 0), Color::new(0, 0, 255)]);
+	}
+
+	#[test]
+	fn rejects_malformed_jasc() {
+		//No count, a count out of range, too few colors, a channel past 255 and a missing channel
+		assert!(decode_palette(b"JASC-PAL\n0100\n").is_err());
+		assert!(decode_palette(b"JASC-PAL\n0100\n0\n").is_err());
+		assert!(decode_palette(b"JASC-PAL\n0100\n257\n").is_err());
+		assert!(decode_palette(b"JASC-PAL\n0100\n2\n1 2 3\n").is_err());
+		assert!(decode_palette(b"JASC-PAL\n0100\n1\n1 2 256\n").is_err());
+		assert!(decode_palette(b"JASC-PAL\n0100\n1\n1 2\n").is_err());
+	}
+
+	#[test]
+	fn reads_the_first_raw_palette() {
+		let mut data = vec![0u8; 256 * 3 * 2];
+		data[3..6].copy_from_slice(&[10, 20, 30]);
+		data[256 * 3..256 * 3 + 3].copy_from_slice(&[255, 255, 255]);
+
+		let colors = decode_palette(&data).unwrap();
+		assert_eq!(colors.len(), 256);
+		assert_eq!(colors[1], Color::new(10, 20, 30));
+		assert_eq!(colors[0], Color::new(0, 0, 0));
+	}
+
+	#[test]
+	fn rejects_malformed_raw_palettes() {
+		assert!(decode_palette(b"").is_err());
+		assert!(decode_palette(&[1, 2]).is_err());
+		assert!(decode_palette(&[1, 2, 3, 4]).is_err());
+	}
+
+	#[test]
+	fn nearest_finds_exact_entries() {
+		let palette = Palette::new(vec![Color::new(0, 0, 0), Color::new(248, 0, 0), Color::new(0, 0, 248)]);
+
+		assert_eq!(palette.nearest(Color::new(248, 0, 0)), 1);
+		assert_eq!(palette.nearest(Color::new(0, 8, 200)), 2);
+		assert_eq!(palette.nearest(Color::new(10, 10, 10)), 0);
+	}
+}
@@ -168,6 +168,16 @@ impl Point3f {
 		self.x*v2.x() + self.y*v2.y() + self.z*v2.z()
 	}
 
+	pub fn cross(&self, v2: Point3f) -> Point3f {
+		Point3f::new(self.y*v2.z() - self.z*v2.y(),
+					 self.z*v2.x() - self.x*v2.z(),
+					 self.x*v2.y() - self.y*v2.x())
+	}
+
+	pub fn length(&self) -> f32 {
+		self.dot(*self).sqrt()
+	}
+
 	//Gets the intersection point between the vector and a plane
 	pub fn intersect_plane(v1: Point3f, v2: Point3f, norm: Point3f) -> Option<Point3f> {
 		let correct_v1: Point3f;
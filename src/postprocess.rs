@@ -0,0 +1,368 @@
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use framebuffer::Framebuffer;
+use texture::invalid_data;
+
+//Screen rows per CRT scanline. One per pixel row would alias into moire once the picture is curved
+const CRT_LINE_HEIGHT: f32 = 3.0;
+
+//Furthest a blur reaches in pixels, a Gaussian reaches three sigmas
+const MAX_BLUR_RADIUS: usize = 64;
+
+/*
+A full screen effect. Passes work on the resolved frame as display values from 0 to 1, after tone
+mapping, so their settings mean the same whatever the lighting
+*/
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Pass {
+	//Raises every channel to 1/gamma, above 1 brightens the midtones
+	Gamma { gamma: f32 },
+
+	//Adds brightness then scales the distance from middle gray by contrast
+	BrightnessContrast { brightness: f32, contrast: f32 },
+
+	//Darkens towards the corners by up to strength, starting radius of the way out from the center
+	Vignette { strength: f32, radius: f32 },
+
+	//Darkens every spacing'th row by strength
+	Scanlines { strength: f32, spacing: usize },
+
+	//Averages a square radius pixels out each way
+	BoxBlur { radius: usize },
+
+	//Weights neighbours by a Gaussian with this standard deviation in pixels
+	GaussianBlur { sigma: f32 },
+
+	//An old monitor: the picture bulges by curvature, rows fade between scanlines by scanlines and an
+	//aperture grille of red, green and blue columns takes away up to mask of the other channels
+	Crt { curvature: f32, scanlines: f32, mask: f32 },
+}
+
+impl Pass {
+	//Parses a line of a post-process file:
+	//	gamma <gamma>
+	//	brightness_contrast <brightness> <contrast>
+	//	vignette <strength> <radius>
+	//	scanlines <strength> <spacing>
+	//	box_blur <radius>
+	//	gaussian_blur <sigma>
+	//	crt <curvature> <scanlines> <mask>
+	//Numbers have to be finite and blurs can't reach past MAX_BLUR_RADIUS
+	pub fn parse(line: &str) -> Option<Pass> {
+		let mut words = line.split_whitespace();
+		let kind = words.next()?;
+		let numbers: Vec<f32> = words.map(|w| w.parse::<f32>().ok().filter(|n| n.is_finite())).collect::<Option<Vec<f32>>>()?;
+		let count = |n: f32| if n >= 1.0 && n.fract() == 0.0 { Some(n as usize) } else { None };
+
+		let pass = match (kind, numbers.len()) {
+			("gamma", 1) if numbers[0] > 0.0 => Pass::Gamma { gamma: numbers[0] },
+			("brightness_contrast", 2) => Pass::BrightnessContrast { brightness: numbers[0], contrast: numbers[1] },
+			("vignette", 2) if numbers[1] < 1.0 => Pass::Vignette { strength: numbers[0], radius: numbers[1] },
+			("scanlines", 2) => Pass::Scanlines { strength: numbers[0], spacing: count(numbers[1])? },
+			("box_blur", 1) if numbers[0] <= MAX_BLUR_RADIUS as f32 => Pass::BoxBlur { radius: count(numbers[0])? },
+			("gaussian_blur", 1) if numbers[0] > 0.0 && numbers[0] * 3.0 <= MAX_BLUR_RADIUS as f32 => Pass::GaussianBlur { sigma: numbers[0] },
+			("crt", 3) => Pass::Crt { curvature: numbers[0], scanlines: numbers[1], mask: numbers[2] },
+			_ => return None,
+		};
+		Some(pass)
+	}
+
+	fn apply(&self, image: &mut Image) {
+		match *self {
+			Pass::Gamma { gamma } => {
+				let power = 1.0 / gamma;
+				image.map(|_, _, c| c.max(0.0).powf(power));
+			}
+			Pass::BrightnessContrast { brightness, contrast } => {
+				image.map(|_, _, c| (c + brightness - 0.5) * contrast + 0.5);
+			}
+			Pass::Vignette { strength, radius } => {
+				let (half_width, half_height) = (image.width as f32 * 0.5, image.height as f32 * 0.5);
+				let corner = (half_width * half_width + half_height * half_height).sqrt();
+
+				image.map_pixels(|x, y, color| {
+					let (dx, dy) = (x as f32 + 0.5 - half_width, y as f32 + 0.5 - half_height);
+					let t = (((dx * dx + dy * dy).sqrt() / corner - radius) / (1.0 - radius)).max(0.0).min(1.0);
+					let scale = 1.0 - strength * t * t;
+					[color[0] * scale, color[1] * scale, color[2] * scale]
+				});
+			}
+			Pass::Scanlines { strength, spacing } => {
+				image.map(|_, y, c| if y % spacing == spacing - 1 { c * (1.0 - strength) } else { c });
+			}
+			Pass::BoxBlur { radius } => {
+				let weights = vec![1.0; radius.min(MAX_BLUR_RADIUS) * 2 + 1];
+				image.convolve(&weights);
+			}
+			Pass::GaussianBlur { sigma } => {
+				//Three standard deviations out holds all but a sliver of the weight
+				let radius = (sigma * 3.0).ceil().min(MAX_BLUR_RADIUS as f32) as isize;
+				let weights: Vec<f32> = (-radius..(radius + 1)).map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp()).collect();
+				image.convolve(&weights);
+			}
+			Pass::Crt { curvature, scanlines, mask } => {
+				let (width, height) = (image.width as f32, image.height as f32);
+
+				image.remap(|x, y, source| {
+					//Pushes each point away from the center by how far off center it is on the other axis
+					let (u, v) = ((x as f32 + 0.5) / width * 2.0 - 1.0, (y as f32 + 0.5) / height * 2.0 - 1.0);
+					let (u, v) = (u * (1.0 + v * v * curvature), v * (1.0 + u * u * curvature));
+					if u.abs() > 1.0 || v.abs() > 1.0 {
+						return [0.0; 3];
+					}
+
+					let (sx, sy) = ((u + 1.0) * 0.5 * width - 0.5, (v + 1.0) * 0.5 * height - 0.5);
+					let color = source.sample(sx, sy);
+
+					//Lines follow the curve, darkest halfway between them
+					let beam = 1.0 - scanlines * (0.5 - 0.5 * (2.0 * PI * sy / CRT_LINE_HEIGHT).cos());
+					let mut grille = [1.0 - mask; 3];
+					grille[x % 3] = 1.0;
+					[color[0] * beam * grille[0], color[1] * beam * grille[1], color[2] * beam * grille[2]]
+				});
+			}
+		}
+	}
+}
+
+/*
+The frame as display values while passes run, so rounding only happens once at the end
+*/
+struct Image {
+	width: usize,
+	height: usize,
+	pixels: Vec<[f32; 3]>,
+
+	//What the frame looked like before the current pass, for passes that read their neighbours
+	source: Vec<[f32; 3]>,
+}
+
+impl Image {
+	fn map<F: Fn(usize, usize, f32) -> f32>(&mut self, f: F) {
+		self.map_pixels(|x, y, color| [f(x, y, color[0]), f(x, y, color[1]), f(x, y, color[2])]);
+	}
+
+	fn map_pixels<F: Fn(usize, usize, [f32; 3]) -> [f32; 3]>(&mut self, f: F) {
+		let width = self.width;
+		for (i, color) in self.pixels.iter_mut().enumerate() {
+			*color = f(i % width, i / width, *color);
+		}
+	}
+
+	//Works out every pixel from a copy of the frame before the pass
+	fn remap<F: Fn(usize, usize, &Source) -> [f32; 3]>(&mut self, f: F) {
+		self.source.clear();
+		self.source.extend_from_slice(&self.pixels);
+
+		let source = Source { width: self.width, height: self.height, pixels: &self.source };
+		let width = self.width;
+		for (i, color) in self.pixels.iter_mut().enumerate() {
+			*color = f(i % width, i / width, &source);
+		}
+	}
+
+	//Blurs with weights centered on each pixel, across then down. Pixels past the edge repeat the edge
+	fn convolve(&mut self, weights: &[f32]) {
+		let total: f32 = weights.iter().sum();
+		let radius = (weights.len() / 2) as isize;
+
+		for &(step_x, step_y) in [(1, 0), (0, 1)].iter() {
+			self.remap(|x, y, source| {
+				let mut sum = [0.0; 3];
+				for (i, weight) in weights.iter().enumerate() {
+					let offset = i as isize - radius;
+					let color = source.pixel(x as isize + offset * step_x, y as isize + offset * step_y);
+					for c in 0..3 {
+						sum[c] += color[c] * weight;
+					}
+				}
+				[sum[0] / total, sum[1] / total, sum[2] / total]
+			});
+		}
+	}
+}
+
+struct Source<'a> {
+	width: usize,
+	height: usize,
+	pixels: &'a [[f32; 3]],
+}
+
+impl<'a> Source<'a> {
+	//Coordinates off the edge are clamped to it
+	fn pixel(&self, x: isize, y: isize) -> [f32; 3] {
+		let x = x.max(0).min(self.width as isize - 1) as usize;
+		let y = y.max(0).min(self.height as isize - 1) as usize;
+		self.pixels[x + y * self.width]
+	}
+
+	//Bilinear filtered, with pixel centers on whole numbers
+	fn sample(&self, x: f32, y: f32) -> [f32; 3] {
+		let (left, top) = (x.floor(), y.floor());
+		let (tx, ty) = (x - left, y - top);
+		let (left, top) = (left as isize, top as isize);
+
+		let (a, b) = (self.pixel(left, top), self.pixel(left + 1, top));
+		let (c, d) = (self.pixel(left, top + 1), self.pixel(left + 1, top + 1));
+
+		let mut color = [0.0; 3];
+		for i in 0..3 {
+			let upper = a[i] + (b[i] - a[i]) * tx;
+			let lower = c[i] + (d[i] - c[i]) * tx;
+			color[i] = upper + (lower - upper) * ty;
+		}
+		color
+	}
+}
+
+/*
+A chain of passes run in order over the resolved frame, loaded from a file with one pass per line.
+Blank lines and lines starting with // are skipped
+*/
+pub struct PostProcess {
+	passes: Vec<Pass>,
+	image: Image,
+}
+
+impl PostProcess {
+	pub fn new(passes: Vec<Pass>) -> PostProcess {
+		PostProcess {
+			passes: passes,
+			image: Image { width: 0, height: 0, pixels: Vec::new(), source: Vec::new() },
+		}
+	}
+
+	pub fn load(path: &Path) -> io::Result<PostProcess> {
+		let mut text = String::new();
+		File::open(path)?.read_to_string(&mut text)?;
+
+		Ok(PostProcess::new(parse_passes(&text)?))
+	}
+
+	pub fn passes(&self) -> &[Pass] {
+		&self.passes
+	}
+
+	pub fn apply(&mut self, framebuffer: &mut Framebuffer) {
+		if self.passes.is_empty() {
+			return;
+		}
+
+		let image = &mut self.image;
+		image.width = framebuffer.width();
+		image.height = framebuffer.height();
+		image.pixels.clear();
+		image.pixels.extend(framebuffer.pixels().chunks(3).map(|p| [p[0] as f32 / 255.0, p[1] as f32 / 255.0, p[2] as f32 / 255.0]));
+
+		for pass in self.passes.iter() {
+			pass.apply(image);
+		}
+
+		let channel = |c: f32| (c * 255.0 + 0.5).max(0.0).min(255.0) as u8;
+		for (color, pixel) in image.pixels.iter().zip(framebuffer.pixels_mut().chunks_mut(3)) {
+			pixel[0] = channel(color[0]);
+			pixel[1] = channel(color[1]);
+			pixel[2] = channel(color[2]);
+		}
+	}
+}
+
+fn parse_passes(text: &str) -> io::Result<Vec<Pass>> {
+	let mut passes = Vec::new();
+
+	for line in text.lines() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with("//") {
+			continue;
+		}
+
+		match Pass::parse(line) {
+			Some(pass) => passes.push(pass),
+			None => return Err(invalid_data(&format!("bad post-process line: {}", line))),
+		}
+	}
+
+	Ok(passes)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_each_pass() {
+		assert_eq!(Pass::parse("gamma 2.2"), Some(Pass::Gamma { gamma: 2.2 }));
+		assert_eq!(Pass::parse("brightness_contrast -0.1 1.5"), Some(Pass::BrightnessContrast { brightness: -0.1, contrast: 1.5 }));
+		assert_eq!(Pass::parse("vignette 0.8 0.4"), Some(Pass::Vignette { strength: 0.8, radius: 0.4 }));
+		assert_eq!(Pass::parse("scanlines 0.3 2"), Some(Pass::Scanlines { strength: 0.3, spacing: 2 }));
+		assert_eq!(Pass::parse("box_blur 3"), Some(Pass::BoxBlur { radius: 3 }));
+		assert_eq!(Pass::parse("  gaussian_blur  1.5 "), Some(Pass::GaussianBlur { sigma: 1.5 }));
+		assert_eq!(Pass::parse("crt 0.1 0.5 0.3"), Some(Pass::Crt { curvature: 0.1, scanlines: 0.5, mask: 0.3 }));
+	}
+
+	#[test]
+	fn rejects_malformed_lines() {
+		assert_eq!(Pass::parse(""), None);
+		assert_eq!(Pass::parse("sharpen 1"), None);
+		assert_eq!(Pass::parse("gamma"), None);
+		assert_eq!(Pass::parse("crt 0.1 0.5"), None);
+		assert_eq!(Pass::parse("vignette strong 0.4"), None);
+		assert_eq!(Pass::parse("gamma 0"), None);
+		assert_eq!(Pass::parse("vignette 0.8 1"), None);
+		assert_eq!(Pass::parse("scanlines 0.3 1.5"), None);
+		assert_eq!(Pass::parse("scanlines 0.3 0"), None);
+		assert_eq!(Pass::parse("box_blur 0"), None);
+		assert_eq!(Pass::parse("gaussian_blur -1"), None);
+		assert_eq!(Pass::parse("gaussian_blur inf"), None);
+		assert_eq!(Pass::parse("gaussian_blur NaN"), None);
+		assert_eq!(Pass::parse("gaussian_blur 1e30"), None);
+		assert_eq!(Pass::parse("box_blur 1000000000"), None);
+		assert_eq!(Pass::parse("gamma inf"), None);
+	}
+
+	#[test]
+	fn blurs_built_by_hand_are_capped() {
+		let mut framebuffer = Framebuffer::new(4, 4);
+		framebuffer.pixels_mut()[0] = 255;
+
+		let mut post = PostProcess::new(vec![Pass::GaussianBlur { sigma: ::std::f32::INFINITY }, Pass::BoxBlur { radius: usize::MAX / 4 }]);
+		post.apply(&mut framebuffer);
+
+		//Still a blur, the one bright red spreads out instead of staying in its corner
+		assert!(framebuffer.pixels()[0] < 255);
+		assert!(framebuffer.pixels().chunks(3).all(|pixel| pixel[1] == 0 && pixel[2] == 0));
+	}
+
+	#[test]
+	fn skips_comments_and_blank_lines() {
+		let passes = parse_passes("// warm and soft\n\ngamma 1.2\n  \n  // blur\nbox_blur 1\n").unwrap();
+		assert_eq!(passes, vec![Pass::Gamma { gamma: 1.2 }, Pass::BoxBlur { radius: 1 }]);
+	}
+
+	#[test]
+	fn rejects_files_with_a_bad_line() {
+		let error = parse_passes("gamma 1.2\nblur 2\n").unwrap_err();
+		assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+	}
+
+	#[test]
+	fn applies_passes_in_order() {
+		let pixels = [64, 128, 255, 0, 32, 200];
+		let mut framebuffer = Framebuffer::new(2, 1);
+		framebuffer.pixels_mut().copy_from_slice(&pixels);
+
+		//Gamma 0.5 squares each channel, then the brightness lifts it
+		let mut post = PostProcess::new(vec![Pass::Gamma { gamma: 0.5 }, Pass::BrightnessContrast { brightness: 0.25, contrast: 1.0 }]);
+		post.apply(&mut framebuffer);
+
+		let expected: Vec<u8> = pixels.iter()
+			.map(|&c| c as f32 / 255.0)
+			.map(|c| ((c * c + 0.25) * 255.0 + 0.5).min(255.0) as u8)
+			.collect();
+		assert_eq!(framebuffer.pixels(), &expected[..]);
+	}
+}
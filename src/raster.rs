@@ -0,0 +1,520 @@
+use pointf::Point2f;
+use pointf::Point3f;
+use framebuffer::Framebuffer;
+use vertex::Interpolate;
+use color::LinearColor;
+use color::BlendMode;
+use color::Rgba;
+use simd::{Edge, SpanEdges, Coverage, SPAN, cover_span};
+use antialias::{MAX_SAMPLES, sample_pattern};
+
+//Anything closer to the camera than this gets clipped away
+pub const NEAR_PLANE: f32 = 0.1;
+
+/*
+A projected vertex, x/y are in pixels and z is still the camera view z
+*/
+#[derive(Copy, Clone)]
+pub struct ScreenVertex<A> {
+	position: Point3f,
+	attributes: A,
+}
+
+impl<A: Interpolate> ScreenVertex<A> {
+	pub fn new(projected: Point3f, attributes: A) -> ScreenVertex<A> {
+		ScreenVertex {
+			position: projected,
+			attributes: attributes,
+		}
+	}
+
+	pub fn position(&self) -> Point3f {
+		self.position
+	}
+
+	pub fn attributes(&self) -> A {
+		self.attributes
+	}
+
+	//Distance in front of the camera, used for perspective correction
+	pub fn w(&self) -> f32 {
+		-self.position.z()
+	}
+}
+
+pub type ScreenTriangle<A> = [ScreenVertex<A>; 3];
+
+//Clips a camera view triangle against the near plane and projects what is left,
+//a triangle with one vertex clipped away becomes a quad so it is split in two
+pub fn clip_triangle<A: Interpolate>(verts: [(Point3f, A); 3]) -> (Option<ScreenTriangle<A>>, Option<ScreenTriangle<A>>) {
+	let mut clipped: Vec<(Point3f, A)> = Vec::with_capacity(4);
+
+	for i in 0..3 {
+		let (a, a_attr) = verts[i];
+		let (b, b_attr) = verts[(i + 1) % 3];
+
+		let a_inside = a.z() <= -NEAR_PLANE;
+		let b_inside = b.z() <= -NEAR_PLANE;
+
+		if a_inside {
+			clipped.push((a, a_attr));
+		}
+
+		if a_inside != b_inside {
+			let t = (-NEAR_PLANE - a.z()) / (b.z() - a.z());
+			let point = a + (b - a) * t;
+
+			clipped.push((Point3f::new(point.x(), point.y(), -NEAR_PLANE), a_attr.lerp(&b_attr, t)));
+		}
+	}
+
+	if clipped.len() < 3 {
+		return (None, None);
+	}
+
+	let projected: Vec<ScreenVertex<A>> = clipped.iter()
+		.map(|&(v, attr)| ScreenVertex::new(v.perspective_project(), attr))
+		.collect();
+
+	let tri1 = [projected[0], projected[1], projected[2]];
+	if projected.len() == 4 {
+		return (Some(tri1), Some([projected[0], projected[2], projected[3]]));
+	}
+
+	(Some(tri1), None)
+}
+
+/*
+Which way a triangle's vertices go round on screen, y pointing down
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Winding {
+	Clockwise,
+	CounterClockwise,
+}
+
+//None for triangles with no area, which have no winding and nothing to draw
+pub fn winding<A: Interpolate>(tri: &ScreenTriangle<A>) -> Option<Winding> {
+	let area = edge(tri[0].position.xy(), tri[1].position.xy(), tri[2].position.x(), tri[2].position.y());
+
+	if area > 0.0 {
+		Some(Winding::Clockwise)
+	} else if area < 0.0 {
+		Some(Winding::CounterClockwise)
+	} else {
+		None
+	}
+}
+
+/*
+Which triangles get skipped before rasterizing, decided by their winding on screen
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum CullMode {
+	None,
+
+	//Skips triangles facing away, the usual for closed geometry
+	Back,
+
+	Front,
+}
+
+impl CullMode {
+	pub fn next(&self) -> CullMode {
+		match *self {
+			CullMode::None => CullMode::Back,
+			CullMode::Back => CullMode::Front,
+			CullMode::Front => CullMode::None,
+		}
+	}
+
+	//front is the winding front facing triangles have on screen
+	pub fn culls<A: Interpolate>(&self, tri: &ScreenTriangle<A>, front: Winding) -> bool {
+		match (*self, winding(tri)) {
+			(_, None) => true,
+			(CullMode::None, _) => false,
+			(CullMode::Back, Some(winding)) => winding != front,
+			(CullMode::Front, Some(winding)) => winding == front,
+		}
+	}
+}
+
+//Signed area of the parallelogram (a, b, p), positive when p is left of a->b
+fn edge(a: Point2f, b: Point2f, px: f32, py: f32) -> f32 {
+	(b.x() - a.x()) * (py - a.y()) - (b.y() - a.y()) * (px - a.x())
+}
+
+/*
+A single pixel being shaded, along with what is needed to work out how fast its attributes change
+*/
+pub struct Fragment<A> {
+	x: usize,
+	y: usize,
+	attributes: A,
+	inv_w: f32,
+
+	//Which of the pixel's samples are covered and passed the depth test, one bit each
+	samples: u32,
+
+	//Screen space gradients of a/w and 1/w, these are constant across a triangle
+	attr_dx: A,
+	attr_dy: A,
+	inv_w_dx: f32,
+	inv_w_dy: f32,
+}
+
+impl<A: Interpolate> Fragment<A> {
+	pub fn x(&self) -> usize {
+		self.x
+	}
+
+	pub fn y(&self) -> usize {
+		self.y
+	}
+
+	pub fn attributes(&self) -> A {
+		self.attributes
+	}
+
+	//How much the attributes change moving one pixel right, from the quotient rule on (a/w) / (1/w)
+	pub fn ddx(&self) -> A {
+		self.attr_dx.sum(&self.attributes.weighted(-self.inv_w_dx)).weighted(1.0 / self.inv_w)
+	}
+
+	//How much the attributes change moving one pixel down
+	pub fn ddy(&self) -> A {
+		self.attr_dy.sum(&self.attributes.weighted(-self.inv_w_dy)).weighted(1.0 / self.inv_w)
+	}
+}
+
+//Fills a triangle into the framebuffer one span at a time, interpolating its attributes
+//with perspective correction and handing them to shade() for every visible pixel.
+//Returns how many pixels were written
+pub fn draw_triangle<A, F>(fb: &mut Framebuffer, tri: &ScreenTriangle<A>, mut shade: F) -> usize
+	where A: Interpolate, F: FnMut(&Fragment<A>) -> LinearColor
+{
+	let mut span = ColorSpan::new();
+	let written = rasterize(fb, tri, true, |fb, fragment| {
+		span.push(fb, fragment, shade(fragment));
+		true
+	});
+
+	span.flush(fb);
+	written
+}
+
+/*
+Shaded colors for up to SPAN pixels of a row, stored together once the row moves past them so
+runs of covered pixels are written several at a time
+*/
+struct ColorSpan {
+	x: usize,
+	y: usize,
+	colors: [LinearColor; SPAN],
+
+	//Pixels to store in each sample's plane, one bit each
+	masks: [u64; MAX_SAMPLES],
+}
+
+impl ColorSpan {
+	fn new() -> ColorSpan {
+		ColorSpan {
+			x: 0,
+			y: 0,
+			colors: [LinearColor::black(); SPAN],
+			masks: [0; MAX_SAMPLES],
+		}
+	}
+
+	//Fragments come along each row left to right, anything past the span starts a new one
+	fn push<A>(&mut self, fb: &mut Framebuffer, fragment: &Fragment<A>, color: LinearColor) {
+		if fragment.y != self.y || fragment.x >= self.x + SPAN {
+			self.flush(fb);
+			self.x = fragment.x;
+			self.y = fragment.y;
+		}
+
+		let pixel = fragment.x - self.x;
+		self.colors[pixel] = color;
+		for sample in 0..fb.samples() {
+			if fragment.samples & (1 << sample) != 0 {
+				self.masks[sample] |= 1 << pixel;
+			}
+		}
+	}
+
+	fn flush(&mut self, fb: &mut Framebuffer) {
+		for sample in 0..fb.samples() {
+			if self.masks[sample] != 0 {
+				fb.set_span_masked(self.x, self.y, sample, &self.colors, self.masks[sample]);
+				self.masks[sample] = 0;
+			}
+		}
+	}
+}
+
+//Like draw_triangle but blends shade()'s premultiplied colors over the framebuffer. Depth is tested but not written so translucent triangles need drawing after opaque
+//ones, back to front
+pub fn draw_triangle_blended<A, F>(fb: &mut Framebuffer, tri: &ScreenTriangle<A>, mode: BlendMode, mut shade: F) -> usize
+	where A: Interpolate, F: FnMut(&Fragment<A>) -> Rgba
+{
+	rasterize(fb, tri, false, |fb, fragment| {
+		fb.blend_samples_masked(fragment.x, fragment.y, fragment.samples, shade(fragment), mode);
+		true
+	})
+}
+
+//Only tests and writes depth, for depth passes into a Framebuffer::depth_only()
+pub fn draw_triangle_depth<A: Interpolate>(fb: &mut Framebuffer, tri: &ScreenTriangle<A>) -> usize {
+	rasterize(fb, tri, true, |_, _| true)
+}
+
+//For indexed color, writes shade()'s palette indices into indices, which holds one per framebuffer
+//pixel. Pixels shade() returns None for are skipped, with write_depth unset that lets translucent
+//surfaces be drawn screen door style since indices can't be blended. Indices can't be averaged
+//either, so this is meant for framebuffers with one sample
+pub fn draw_triangle_indexed<A, F>(fb: &mut Framebuffer, tri: &ScreenTriangle<A>, indices: &mut [u8], write_depth: bool, mut shade: F) -> usize
+	where A: Interpolate, F: FnMut(&Fragment<A>) -> Option<u8>
+{
+	let (left, top, width) = (fb.left(), fb.top(), fb.width());
+	rasterize(fb, tri, write_depth, |_, fragment| {
+		match shade(fragment) {
+			Some(index) => {
+				indices[(fragment.x - left) + (fragment.y - top) * width] = index;
+				true
+			}
+			None => false,
+		}
+	})
+}
+
+//Walks every pixel covered by the triangle that passes the depth test, visit() says whether it wrote
+//the pixel and the number written is returned. With multisampling coverage and depth are tested at
+//each sample and a pixel is visited once for all of its samples that passed
+fn rasterize<A, F>(fb: &mut Framebuffer, tri: &ScreenTriangle<A>, write_depth: bool, mut visit: F) -> usize
+	where A: Interpolate, F: FnMut(&mut Framebuffer, &Fragment<A>) -> bool
+{
+	let (p0, p1, p2) = (tri[0].position.xy(), tri[1].position.xy(), tri[2].position.xy());
+
+	let area = edge(p0, p1, p2.x(), p2.y());
+	if area == 0.0 || !area.is_finite() {
+		return 0;
+	}
+
+	let (left, top) = (fb.left() as f32, fb.top() as f32);
+	let min_x = p0.x().min(p1.x()).min(p2.x()).floor().max(left);
+	let max_x = p0.x().max(p1.x()).max(p2.x()).ceil().min(left + fb.width() as f32 - 1.0);
+	let min_y = p0.y().min(p1.y()).min(p2.y()).floor().max(top);
+	let max_y = p0.y().max(p1.y()).max(p2.y()).ceil().min(top + fb.height() as f32 - 1.0);
+
+	if min_x > max_x || min_y > max_y {
+		return 0;
+	}
+
+	let (min_x, max_x) = (min_x as usize, max_x as usize);
+	let (min_y, max_y) = (min_y as usize, max_y as usize);
+
+	//Attributes are interpolated as a/w and 1/w, then divided back per pixel
+	let inv_area = 1.0 / area;
+	let inv_w = [1.0 / tri[0].w(), 1.0 / tri[1].w(), 1.0 / tri[2].w()];
+	let attr = [tri[0].attributes.weighted(inv_w[0]),
+				tri[1].attributes.weighted(inv_w[1]),
+				tri[2].attributes.weighted(inv_w[2])];
+
+	//How much each barycentric weight changes per pixel along a span, and from one span to the next
+	let step = [(p1.y() - p2.y()) * inv_area,
+				(p2.y() - p0.y()) * inv_area,
+				(p0.y() - p1.y()) * inv_area];
+	let step_y = [(p2.x() - p1.x()) * inv_area,
+				  (p0.x() - p2.x()) * inv_area,
+				  (p1.x() - p0.x()) * inv_area];
+
+	let mut fragment = Fragment {
+		x: 0,
+		y: 0,
+		attributes: tri[0].attributes,
+		inv_w: inv_w[0],
+		samples: 0,
+		attr_dx: attr[0].weighted(step[0]).sum(&attr[1].weighted(step[1])).sum(&attr[2].weighted(step[2])),
+		attr_dy: attr[0].weighted(step_y[0]).sum(&attr[1].weighted(step_y[1])).sum(&attr[2].weighted(step_y[2])),
+		inv_w_dx: step[0] * inv_w[0] + step[1] * inv_w[1] + step[2] * inv_w[2],
+		inv_w_dy: step_y[0] * inv_w[0] + step_y[1] * inv_w[1] + step_y[2] * inv_w[2],
+	};
+
+	let pattern = sample_pattern(fb.samples());
+	let mut coverage: Vec<Coverage> = pattern.iter().map(|_| Coverage::new()).collect();
+	let corner_edges = [Edge::new(p1, p2, area), Edge::new(p2, p0, area), Edge::new(p0, p1, area)];
+	let mut edges = [SpanEdges::new(corner_edges, step, inv_w); MAX_SAMPLES];
+	let mut written = 0;
+
+	for y in min_y..(max_y + 1) {
+		for (sample, &(offset_x, offset_y)) in pattern.iter().enumerate() {
+			let (px, py) = (min_x as f32 + offset_x, y as f32 + offset_y);
+			edges[sample].start_row(px, py);
+			edges[sample].weights = [edge(p1, p2, px, py) * inv_area,
+									 edge(p2, p0, px, py) * inv_area,
+									 edge(p0, p1, px, py) * inv_area];
+		}
+
+		//Coverage and depth are tested a span of up to SPAN pixels at a time
+		let mut x = min_x;
+		while x <= max_x {
+			let count = SPAN.min(max_x + 1 - x);
+
+			let mut covered = 0;
+			for sample in 0..pattern.len() {
+				cover_span(&edges[sample], fb.depth_span(x, y, sample, count), &mut coverage[sample]);
+				covered |= coverage[sample].mask;
+			}
+
+			while covered != 0 {
+				let pixel = covered.trailing_zeros() as usize;
+				covered &= covered - 1;
+
+				let mut samples = 0u32;
+				for sample in 0..pattern.len() {
+					if coverage[sample].mask & (1 << pixel) != 0 && fb.depth_test(x + pixel, y, sample, coverage[sample].inv_w[pixel], write_depth) {
+						samples |= 1 << sample;
+					}
+				}
+				if samples == 0 {
+					continue;
+				}
+
+				//Shaded once at the first sample that passed, which is always inside the triangle
+				let first = &coverage[samples.trailing_zeros() as usize];
+				let pixel_inv_w = first.inv_w[pixel];
+				let b = first.weights(pixel);
+
+				fragment.x = x + pixel;
+				fragment.y = y;
+				fragment.inv_w = pixel_inv_w;
+				fragment.samples = samples;
+				fragment.attributes = attr[0].weighted(b[0])
+					.sum(&attr[1].weighted(b[1]))
+					.sum(&attr[2].weighted(b[2]))
+					.weighted(1.0 / pixel_inv_w);
+
+				if visit(fb, &fragment) {
+					written += 1;
+				}
+			}
+
+			for sample in edges[..pattern.len()].iter_mut() {
+				sample.advance(SPAN);
+			}
+			x += SPAN;
+		}
+	}
+
+	written
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn triangle(a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> ScreenTriangle<f32> {
+		[ScreenVertex::new(Point3f::new(a.0, a.1, -1.0), 0.0),
+		 ScreenVertex::new(Point3f::new(b.0, b.1, -1.0), 0.0),
+		 ScreenVertex::new(Point3f::new(c.0, c.1, -1.0), 0.0)]
+	}
+
+	//Adds up how many times each sample is drawn over by a fan of triangles around center
+	fn draw_fan(fb: &mut Framebuffer, center: (f32, f32), corners: &[(f32, f32)]) -> usize {
+		let mut written = 0;
+		for i in 0..corners.len() {
+			let tri = triangle(center, corners[i], corners[(i + 1) % corners.len()]);
+			written += draw_triangle_blended(fb, &tri, BlendMode::Additive, |_| Rgba::new(1.0, 0.0, 0.0, 1.0));
+		}
+		written
+	}
+
+	//Whether the point is strictly inside the convex polygon, so no triangle should miss it
+	fn strictly_inside(corners: &[(f32, f32)], px: f64, py: f64) -> bool {
+		let sides: Vec<f64> = (0..corners.len()).map(|i| {
+			let (a, b) = (corners[i], corners[(i + 1) % corners.len()]);
+			(b.0 as f64 - a.0 as f64) * (py - a.1 as f64) - (b.1 as f64 - a.1 as f64) * (px - a.0 as f64)
+		}).collect();
+
+		sides.iter().all(|&s| s > 1e-6) || sides.iter().all(|&s| s < -1e-6)
+	}
+
+	//Checks the first sample of every pixel
+	fn assert_covered_once(fb: &Framebuffer, corners: &[(f32, f32)]) {
+		let (offset_x, offset_y) = sample_pattern(fb.samples())[0];
+
+		for y in 0..fb.height() {
+			for x in 0..fb.width() {
+				let count = fb.pixel(x, y).r();
+				assert!(count <= 1.0, "pixel {}, {} drawn {} times", x, y, count);
+				if strictly_inside(corners, x as f64 + offset_x as f64, y as f64 + offset_y as f64) {
+					assert_eq!(count, 1.0, "pixel {}, {} missed", x, y);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn split_quad_covers_each_pixel_once() {
+		let mut fb = Framebuffer::new(80, 80);
+		let quad = [(8.0, 8.0), (72.0, 8.0), (72.0, 72.0), (8.0, 72.0)];
+
+		//Split on its diagonal either way round
+		for &(first, second) in [([0, 1, 2], [0, 2, 3]), ([1, 2, 3], [1, 3, 0])].iter() {
+			fb.clear(LinearColor::black());
+			let tri = |i: [usize; 3]| triangle(quad[i[0]], quad[i[1]], quad[i[2]]);
+			let shade = |_: &Fragment<f32>| Rgba::new(1.0, 0.0, 0.0, 1.0);
+
+			let written = draw_triangle_blended(&mut fb, &tri(first), BlendMode::Additive, shade)
+				+ draw_triangle_blended(&mut fb, &tri(second), BlendMode::Additive, shade);
+
+			assert_eq!(written, 64 * 64);
+			assert_covered_once(&fb, &quad);
+		}
+	}
+
+	#[test]
+	fn fan_covers_each_pixel_once() {
+		//Some edges run along rows and columns of pixel centers, the rest are off the grid
+		let corners = [(3.5, 40.5), (20.7, 4.1), (40.5, 2.5), (61.2, 17.9), (66.5, 40.5), (58.5, 58.5), (40.5, 77.5), (9.8, 63.3)];
+
+		for &samples in [1, 4].iter() {
+			let mut fb = Framebuffer::new(80, 80);
+			fb.set_samples(samples);
+			fb.clear(LinearColor::black());
+
+			draw_fan(&mut fb, (40.5, 40.5), &corners);
+			assert_covered_once(&fb, &corners);
+		}
+	}
+
+	#[test]
+	fn alpha_blends_spans_in_linear_light() {
+		let background = LinearColor::new(0.2, 0.4, 0.8);
+		let glass = LinearColor::new(1.0, 0.5, 0.0);
+		let mut fb = Framebuffer::new(SPAN + 16, 4);
+		fb.clear(background);
+
+		//Two layers of quarter covering glass across every row, each one a span and a bit wide
+		let quad = [(0.0, 0.0), ((SPAN + 16) as f32, 0.0), ((SPAN + 16) as f32, 4.0), (0.0, 4.0)];
+		for _ in 0..2 {
+			for &i in [[0, 1, 2], [0, 2, 3]].iter() {
+				let tri = triangle(quad[i[0]], quad[i[1]], quad[i[2]]);
+				draw_triangle_blended(&mut fb, &tri, BlendMode::Alpha, |_| Rgba::premultiplied(glass, 0.25));
+			}
+		}
+
+		//Over is src * a + dst * (1 - a) on the linear values, twice
+		let once = glass * 0.25 + background * 0.75;
+		let expected = glass * 0.25 + once * 0.75;
+		for y in 0..fb.height() {
+			for x in 0..fb.width() {
+				let color = fb.pixel(x, y);
+				for &(channel, want) in [(color.r(), expected.r()), (color.g(), expected.g()), (color.b(), expected.b())].iter() {
+					assert!((channel - want).abs() < 1e-6, "pixel {}, {} is {:?}, expected {:?}", x, y, color, expected);
+				}
+			}
+		}
+	}
+}
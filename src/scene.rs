@@ -0,0 +1,528 @@
+use std::cmp::Ordering;
+use std::time::Instant;
+
+use pointf::Point3f;
+use color::LinearColor;
+use color::BlendMode;
+use color::Rgba;
+use color::ToneMapper;
+use vertex::Attributes;
+use framebuffer::Framebuffer;
+use raster::*;
+use texture::FilterMode;
+use texture::IndexedTexture;
+use mesh::{Mesh, MeshVertex};
+use lighting::*;
+use fog::Fog;
+use shadow::ShadowMap;
+use map::Map;
+use tiles::TileRenderer;
+use palette::{Palette, Colormap};
+use dither::{Quantizer, OutputFormat, Dither, ordered_threshold};
+use stats::FrameStats;
+use debug::{DebugView, id_color, heat, depth_gray};
+use wireframe::{WireframeMode, draw_wireframe};
+use antialias::{Antialias, Fxaa};
+use postprocess::PostProcess;
+use shader::{VertexShader, FragmentShader};
+
+//Light fades to half this far from the camera
+pub const DIMINISH_DISTANCE: f32 = 40.0;
+
+//Width and height of the sun's shadow map in texels
+const SHADOW_MAP_SIZE: usize = 2048;
+
+//Map triangles wind counter-clockwise around their normals, the y down world flips that on screen
+const FRONT_FACE: Winding = Winding::Clockwise;
+
+//Light levels in the indexed color mode's colormap
+const COLORMAP_LEVELS: usize = 32;
+
+//Scales the scene's light before tone mapping
+const EXPOSURE: f32 = 1.0;
+
+//Farthest the depth view fades to black over, fog that ends sooner shortens it
+const DEPTH_VIEW_RANGE: f32 = 32.0;
+
+/*
+A loaded map with its lights and the settings it is drawn with. render() draws a frame from a camera
+into framebuffer(), ready to be shown
+*/
+pub struct Scene {
+	map: Map,
+	mesh: Mesh,
+	fog: Option<Fog>,
+
+	lighting: Lighting,
+	headlamp: usize,
+	headlamp_color: LinearColor,
+
+	palette: Palette,
+	colormap: Colormap,
+	indexed_textures: Vec<IndexedTexture>,
+
+	filter: FilterMode,
+	tone_mapper: ToneMapper,
+	quantizer: Quantizer,
+	indexed: bool,
+	cull: CullMode,
+	debug_view: DebugView,
+	wireframe: WireframeMode,
+	antialias: Antialias,
+	fxaa: Fxaa,
+	post_process: PostProcess,
+	post_processing: bool,
+	vertex_shader: Option<Box<dyn VertexShader<Attributes, Vertex = MeshVertex>>>,
+	fragment_shader: Option<Box<dyn FragmentShader<Attributes> + Sync>>,
+
+	//Edge color for each mesh triangle
+	wireframe_colors: Vec<LinearColor>,
+
+	framebuffer: Framebuffer,
+	indices: Vec<u8>,
+	tile_renderer: TileRenderer<Attributes>,
+
+	//Triangles drawn in the last frame, kept for their edges when there's a wireframe
+	wires: Vec<(usize, ScreenTriangle<Attributes>)>,
+	stats: FrameStats,
+}
+
+impl Scene {
+	//palette is used by the indexed color mode and the indexed output format
+	pub fn new(map: Map, palette: Palette, width: usize, height: usize) -> Scene {
+		let mesh = map.mesh();
+		let fog = map.fog();
+
+		let colormap = Colormap::new(&palette, COLORMAP_LEVELS);
+		let indexed_textures = map.textures().iter().map(|t| t.to_indexed(&palette)).collect();
+		let wireframe_colors = vec![LinearColor::new(1.0, 1.0, 1.0); mesh.triangle_count()];
+
+		//A dim fill light, a sun coming down at an angle and a lamp that follows the camera, bright
+		//enough near the camera to go past white for the tone mapper to roll off
+		let mut lighting = Lighting::new(ShadeMode::Gouraud);
+		lighting.set_diminish(Some(DIMINISH_DISTANCE));
+		lighting.add_light(Light::Ambient { color: LinearColor::new(0.15, 0.15, 0.18) });
+		let sun_direction = Point3f::new(0.4, 1.0, 0.6);
+		let sun = lighting.add_light(Light::Directional { direction: sun_direction, color: LinearColor::new(0.9, 0.8, 0.7) });
+		if let Some(shadow_map) = ShadowMap::render(&mesh, sun_direction, SHADOW_MAP_SIZE) {
+			lighting.set_shadow_map(sun, shadow_map);
+		}
+		let headlamp_color = LinearColor::new(2.0, 1.8, 1.5);
+		let headlamp = lighting.add_light(Light::Point { position: Point3f::new(0.0, 0.0, 0.0), color: headlamp_color, attenuation: (1.0, 0.02, 0.002) });
+
+		Scene {
+			map: map,
+			mesh: mesh,
+			fog: fog,
+			lighting: lighting,
+			headlamp: headlamp,
+			headlamp_color: headlamp_color,
+			palette: palette,
+			colormap: colormap,
+			indexed_textures: indexed_textures,
+			filter: FilterMode::Trilinear,
+			tone_mapper: ToneMapper::Aces,
+			quantizer: Quantizer::new(OutputFormat::Rgb24, Dither::Ordered),
+			indexed: false,
+			cull: CullMode::Back,
+			debug_view: DebugView::Shaded,
+			wireframe: WireframeMode::Off,
+			antialias: Antialias::Off,
+			fxaa: Fxaa::new(),
+			post_process: PostProcess::new(Vec::new()),
+			post_processing: true,
+			vertex_shader: None,
+			fragment_shader: None,
+			wireframe_colors: wireframe_colors,
+			framebuffer: Framebuffer::new(width, height),
+			indices: vec![0u8; width * height],
+			tile_renderer: TileRenderer::new(width, height, TileRenderer::<Attributes>::available_threads()),
+			wires: Vec::new(),
+			stats: FrameStats::default(),
+		}
+	}
+
+	pub fn map(&self) -> &Map {
+		&self.map
+	}
+
+	pub fn palette(&self) -> &Palette {
+		&self.palette
+	}
+
+	pub fn lighting(&self) -> &Lighting {
+		&self.lighting
+	}
+
+	pub fn lighting_mut(&mut self) -> &mut Lighting {
+		&mut self.lighting
+	}
+
+	pub fn set_filter(&mut self, filter: FilterMode) {
+		self.filter = filter;
+	}
+
+	pub fn filter(&self) -> FilterMode {
+		self.filter
+	}
+
+	pub fn set_tone_mapper(&mut self, tone_mapper: ToneMapper) {
+		self.tone_mapper = tone_mapper;
+	}
+
+	pub fn tone_mapper(&self) -> ToneMapper {
+		self.tone_mapper
+	}
+
+	pub fn quantizer(&self) -> &Quantizer {
+		&self.quantizer
+	}
+
+	pub fn quantizer_mut(&mut self) -> &mut Quantizer {
+		&mut self.quantizer
+	}
+
+	pub fn set_indexed(&mut self, indexed: bool) {
+		self.indexed = indexed;
+	}
+
+	pub fn indexed(&self) -> bool {
+		self.indexed
+	}
+
+	pub fn set_cull(&mut self, cull: CullMode) {
+		self.cull = cull;
+	}
+
+	pub fn cull(&self) -> CullMode {
+		self.cull
+	}
+
+	//Debug views take over from indexed color while they're on
+	pub fn set_debug_view(&mut self, debug_view: DebugView) {
+		self.debug_view = debug_view;
+	}
+
+	pub fn debug_view(&self) -> DebugView {
+		self.debug_view
+	}
+
+	pub fn set_wireframe(&mut self, wireframe: WireframeMode) {
+		self.wireframe = wireframe;
+	}
+
+	pub fn wireframe(&self) -> WireframeMode {
+		self.wireframe
+	}
+
+	//Edges are white until given a color, tri is a mesh triangle index
+	pub fn set_wireframe_color(&mut self, tri: usize, color: LinearColor) {
+		self.wireframe_colors[tri] = color;
+	}
+
+	//Indexed color can't average samples or blend, so it's always drawn without anti-aliasing
+	pub fn set_antialias(&mut self, antialias: Antialias) {
+		self.antialias = antialias;
+	}
+
+	pub fn antialias(&self) -> Antialias {
+		self.antialias
+	}
+
+	//Runs over the shaded frame after anti-aliasing and before quantizing, debug views, lines only
+	//wireframes and indexed color are shown without it
+	pub fn set_post_process(&mut self, post_process: PostProcess) {
+		self.post_process = post_process;
+	}
+
+	pub fn post_process(&self) -> &PostProcess {
+		&self.post_process
+	}
+
+	//Turns the post-process chain off without dropping it
+	pub fn set_post_processing(&mut self, post_processing: bool) {
+		self.post_processing = post_processing;
+	}
+
+	pub fn post_processing(&self) -> bool {
+		self.post_processing
+	}
+
+	//Runs once per frame on every vertex of the mesh, with its lighting already worked out, and the
+	//triangles are drawn from what it returns. None draws the mesh as it is. Applies to every view so wireframes and debug views follow it
+	pub fn set_vertex_shader(&mut self, vertex_shader: Option<Box<dyn VertexShader<Attributes, Vertex = MeshVertex>>>) {
+		self.vertex_shader = vertex_shader;
+	}
+
+	pub fn has_vertex_shader(&self) -> bool {
+		self.vertex_shader.is_some()
+	}
+
+	//Replaces the built in texturing, Phong lighting and fog for the shaded view, None goes back to
+	//them. Flat and Gouraud lighting are already in the fragment's color, apart from the shadowed
+	//light which is in its shadowed() for Lighting::visibility() to scale. Indexed color can't take
+	//colors from a shader so it keeps its own shading
+	pub fn set_fragment_shader(&mut self, fragment_shader: Option<Box<dyn FragmentShader<Attributes> + Sync>>) {
+		self.fragment_shader = fragment_shader;
+	}
+
+	pub fn has_fragment_shader(&self) -> bool {
+		self.fragment_shader.is_some()
+	}
+
+	pub fn set_threads(&mut self, threads: usize) {
+		self.tile_renderer.set_threads(threads);
+	}
+
+	pub fn threads(&self) -> usize {
+		self.tile_renderer.threads()
+	}
+
+	pub fn framebuffer(&self) -> &Framebuffer {
+		&self.framebuffer
+	}
+
+	//For drawing overlays onto the resolved frame
+	pub fn framebuffer_mut(&mut self) -> &mut Framebuffer {
+		&mut self.framebuffer
+	}
+
+	//Counters and stage times from the last render()
+	pub fn stats(&self) -> &FrameStats {
+		&self.stats
+	}
+
+	//Draws a frame seen from cam_pos looking along cam_rot and resolves it for display
+	pub fn render(&mut self, cam_pos: Point3f, cam_rot: Point3f) {
+		let start = Instant::now();
+		self.lighting.set_light(self.headlamp, Light::Point { position: cam_pos, color: self.headlamp_color, attenuation: (1.0, 0.02, 0.002) });
+
+		self.mesh.transform(cam_pos, cam_rot);
+		self.mesh.light(&self.lighting, cam_pos);
+		match self.vertex_shader {
+			Some(ref vertex_shader) => self.mesh.shade_vertices(&**vertex_shader),
+			None => self.mesh.clear_shaded(),
+		}
+		self.wires.clear();
+
+		let Scene {
+			ref map, ref mesh, fog, ref lighting, ref palette, ref colormap, ref indexed_textures,
+			filter, tone_mapper, ref mut quantizer, indexed, cull, debug_view, wireframe, ref wireframe_colors, antialias, ref mut fxaa,
+			ref mut post_process, post_processing, ref fragment_shader,
+			ref mut framebuffer, ref mut indices, ref mut tile_renderer, ref mut wires, ref mut stats, ..
+		} = *self;
+
+		//Edges on their own only need the triangles' depth to hide the ones behind them
+		let lines_only = wireframe.lines_only();
+		let indexed = indexed && debug_view == DebugView::Shaded && !lines_only;
+
+		//Only changes anything, and clears the buffers, when the sample count does
+		let samples = if indexed { 1 } else { antialias.samples() };
+		framebuffer.set_samples(samples);
+		tile_renderer.set_samples(samples);
+
+		*stats = FrameStats::default();
+		stats.screen_pixels = framebuffer.width() * framebuffer.height();
+		stats.transform_time = start.elapsed();
+		let start = Instant::now();
+
+		let background = fog.map(|f| f.color()).unwrap_or(LinearColor::black());
+		if indexed {
+			framebuffer.clear(background);
+			let background = palette.nearest(background.to_srgb());
+			for index in indices.iter_mut() {
+				*index = background;
+			}
+		}
+
+		//Flat and Gouraud have already lit the vertex colors apart from the shadowed light, its shadow
+		//is looked up here for every pixel so shadow edges can cross a triangle
+		let vertex_color = |attributes: &Attributes| -> LinearColor {
+			let shadowed = attributes.shadowed();
+			if shadowed == LinearColor::black() {
+				return attributes.color();
+			}
+
+			attributes.color() + shadowed * lighting.visibility(cam_pos - attributes.view(), attributes.normal(), cam_pos)
+		};
+
+		//Shades one pixel of triangle i, shared by the opaque and translucent passes
+		let shade = |i: usize, fragment: &Fragment<Attributes>| -> LinearColor {
+			let attributes = fragment.attributes();
+			let color = vertex_color(&attributes);
+			let albedo = match mesh.texture(i) {
+				Some(t) => map.textures()[t].sample(attributes.uv(), fragment.ddx().uv(), fragment.ddy().uv(), filter) * color,
+				None => color,
+			};
+
+			//Phong lights each pixel, shadow included
+			let lit = if lighting.mode() == ShadeMode::Phong {
+				lighting.illuminate(cam_pos - attributes.view(), attributes.normal(), cam_pos, albedo, mesh.material(i))
+			} else {
+				albedo
+			};
+
+			let lit = lighting.diminish(lit, attributes.depth());
+			match fog {
+				Some(fog) => fog.apply(lit, attributes.depth()),
+				None => lit,
+			}
+		};
+
+		//The indexed color version, lighting goes through the colormap so the result stays in the
+		//palette. Fog and Phong shading are left out
+		let shade_indexed = |i: usize, fragment: &Fragment<Attributes>| -> u8 {
+			let attributes = fragment.attributes();
+			let color = vertex_color(&attributes);
+
+			//Textured triangles are lit from white so the vertex color is just the light. Untextured
+			//ones take their color from the palette and the light is what's left once it's divided out
+			let (index, light) = match mesh.texture(i) {
+				Some(t) => {
+					let index = indexed_textures[t].sample(attributes.uv(), fragment.ddx().uv(), fragment.ddy().uv());
+					(index, color.luminance())
+				}
+				None => {
+					let base = mesh.base_color(i);
+					(palette.nearest(base.to_srgb()), color.luminance() / base.luminance().max(0.001))
+				}
+			};
+
+			colormap.light(index, light * lighting.diminish_scale(attributes.depth()))
+		};
+
+		//Flat colors for the debug views, overdraw is counted by blending instead
+		let depth_range = fog.map_or(DEPTH_VIEW_RANGE, |f| f.max_distance().min(DEPTH_VIEW_RANGE));
+		let shade_debug = |i: usize, fragment: &Fragment<Attributes>| -> LinearColor {
+			match debug_view {
+				DebugView::Depth => depth_gray(fragment.attributes().depth(), depth_range),
+				DebugView::SectorId => id_color(mesh.sector(i)),
+				_ => id_color(i),
+			}
+		};
+
+		let mut opaque: Vec<(usize, ScreenTriangle<Attributes>)> = Vec::new();
+		let mut translucent: Vec<(f32, usize, ScreenTriangle<Attributes>, BlendMode)> = Vec::new();
+
+		for i in 0..mesh.triangle_count() {
+			stats.submitted += 1;
+			let (screen_tri_1, screen_tri_2) = mesh.to_screen_space(i);
+
+			//Whole triangles lost in the fog can be skipped
+			if let (Some(fog), Some(tri)) = (fog, screen_tri_1) {
+				if tri.iter().chain(screen_tri_2.iter().flat_map(|t| t.iter())).all(|v| v.w() > fog.max_distance()) {
+					stats.clipped += 1;
+					continue;
+				}
+			}
+			if screen_tri_1.is_none() && screen_tri_2.is_none() {
+				stats.clipped += 1;
+			}
+
+			for screen_tri in screen_tri_1.iter().chain(screen_tri_2.iter()) {
+				if cull.culls(screen_tri, FRONT_FACE) {
+					stats.culled += 1;
+					continue;
+				}
+
+				//Overdraw adds one for every triangle covering a pixel and ignores depth, the other
+				//debug views draw everything as if it were opaque
+				match (debug_view, mesh.blend(i)) {
+					_ if lines_only => tile_renderer.draw(screen_tri, i),
+					(DebugView::Overdraw, _) => tile_renderer.draw_blended(screen_tri, i, BlendMode::Additive),
+					(DebugView::Shaded, Some(mode)) => {
+						let depth = (screen_tri[0].w() + screen_tri[1].w() + screen_tri[2].w()) / 3.0;
+						translucent.push((depth, i, *screen_tri, mode));
+					}
+					(DebugView::Shaded, None) if indexed => opaque.push((i, *screen_tri)),
+					_ => tile_renderer.draw(screen_tri, i),
+				}
+				stats.rasterized += 1;
+				if wireframe != WireframeMode::Off {
+					wires.push((i, *screen_tri));
+				}
+			}
+		}
+
+		//Translucent triangles go on top of everything else, furthest first so nearer ones cover them
+		translucent.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+		if !indexed {
+			for &(_, i, ref screen_tri, mode) in &translucent {
+				tile_renderer.draw_blended(screen_tri, i, mode);
+			}
+		}
+
+		stats.setup_time = start.elapsed();
+		let start = Instant::now();
+
+		if lines_only {
+			stats.pixels_written = tile_renderer.render(framebuffer, LinearColor::black(), |_, _| LinearColor::black(), |_, _| {
+				Rgba::from(LinearColor::black())
+			});
+		} else if debug_view != DebugView::Shaded {
+			stats.pixels_written = tile_renderer.render(framebuffer, LinearColor::black(), &shade_debug, |_, _| {
+				Rgba::new(1.0, 1.0, 1.0, 1.0)
+			});
+		} else if indexed {
+			for &(i, ref screen_tri) in &opaque {
+				stats.pixels_written += draw_triangle_indexed(framebuffer, screen_tri, indices, true, |fragment| Some(shade_indexed(i, fragment)));
+			}
+
+			//Indices can't be blended, so indexed color covers a share of the pixels matching alpha
+			for &(_, i, ref screen_tri, _) in &translucent {
+				stats.pixels_written += draw_triangle_indexed(framebuffer, screen_tri, indices, false, |fragment| {
+					if fragment.attributes().alpha() > ordered_threshold(fragment.x(), fragment.y()) {
+						Some(shade_indexed(i, fragment))
+					} else {
+						None
+					}
+				});
+			}
+		} else if let Some(ref fragment_shader) = *fragment_shader {
+			stats.pixels_written = tile_renderer.render(framebuffer, background, |_, fragment| fragment_shader.shade(fragment), |_, fragment| {
+				Rgba::premultiplied(fragment_shader.shade(fragment), fragment.attributes().alpha())
+			});
+		} else {
+			stats.pixels_written = tile_renderer.render(framebuffer, background, &shade, |i, fragment| {
+				Rgba::premultiplied(shade(i, fragment), fragment.attributes().alpha())
+			});
+		}
+
+		//Overdraw is drawn without depth, so what it covered is wherever the count went up
+		stats.covered_pixels = if debug_view == DebugView::Overdraw && !lines_only {
+			framebuffer.count_colors(|count| count.r() > 0.0)
+		} else {
+			framebuffer.covered_pixels()
+		};
+
+		stats.raster_time = start.elapsed();
+		let start = Instant::now();
+
+		match debug_view {
+			_ if lines_only => framebuffer.resolve(ToneMapper::Clamp, 1.0),
+			DebugView::Shaded if indexed => framebuffer.resolve_indexed(indices, palette),
+			DebugView::Shaded => {
+				framebuffer.resolve(tone_mapper, EXPOSURE);
+				if antialias == Antialias::Fxaa {
+					fxaa.apply(framebuffer);
+				}
+				if post_processing {
+					post_process.apply(framebuffer);
+				}
+				quantizer.apply(framebuffer);
+			}
+			//Debug colors are shown as they are
+			DebugView::Overdraw => {
+				framebuffer.map_colors(|count| heat(count.r()));
+				framebuffer.resolve(ToneMapper::Clamp, 1.0);
+			}
+			_ => framebuffer.resolve(ToneMapper::Clamp, 1.0),
+		}
+
+		for &(i, ref screen_tri) in wires.iter() {
+			draw_wireframe(framebuffer, screen_tri, wireframe_colors[i], wireframe.depth_test());
+		}
+
+		stats.resolve_time = start.elapsed();
+	}
+}
@@ -0,0 +1,159 @@
+use pointf::Point3f;
+use framebuffer::Framebuffer;
+use raster::*;
+use vertex::Interpolate;
+use color::LinearColor;
+
+/*
+Runs once for every vertex a Pipeline draws, or once a frame on every vertex of the map's mesh when
+it is a Scene's with MeshVertex for Vertex. V is the varyings, whatever the fragment shader needs interpolated
+across the triangle, and Vertex is whatever the caller keeps per vertex
+*/
+pub trait VertexShader<V: Interpolate> {
+	type Vertex;
+
+	//Where the vertex ends up in camera view space, looking down -z, and its varyings
+	fn shade(&self, vertex: &Self::Vertex) -> (Point3f, V);
+}
+
+/*
+Runs once for every pixel a triangle covers that passes the depth test, the fragment carries the
+varyings interpolated with perspective correction. Shaders are shared between the tile renderer's
+threads so they only get &self
+*/
+pub trait FragmentShader<V: Interpolate> {
+	fn shade(&self, fragment: &Fragment<V>) -> LinearColor;
+}
+
+//So a closure will do for simple fragment shaders
+impl<V: Interpolate, F: Fn(&Fragment<V>) -> LinearColor> FragmentShader<V> for F {
+	fn shade(&self, fragment: &Fragment<V>) -> LinearColor {
+		self(fragment)
+	}
+}
+
+/*
+Draws indexed triangles through a vertex and a fragment shader, clipping against the near plane
+and culling on the way. Keeps the shaded vertices between calls
+*/
+pub struct Pipeline<V> {
+	cull: CullMode,
+	front: Winding,
+	shaded: Vec<(Point3f, V)>,
+}
+
+impl<V: Interpolate> Pipeline<V> {
+	//front is the winding front facing triangles have on screen
+	pub fn new(cull: CullMode, front: Winding) -> Pipeline<V> {
+		Pipeline {
+			cull: cull,
+			front: front,
+			shaded: Vec::new(),
+		}
+	}
+
+	pub fn set_cull(&mut self, cull: CullMode) {
+		self.cull = cull;
+	}
+
+	pub fn cull(&self) -> CullMode {
+		self.cull
+	}
+
+	//Shades every vertex once, however many triangles share it, then draws a triangle for every
+	//three indices. Returns how many pixels were written
+	pub fn draw<VS, FS>(&mut self, fb: &mut Framebuffer, vertices: &[VS::Vertex], indices: &[usize], vertex_shader: &VS, fragment_shader: &FS) -> usize
+		where VS: VertexShader<V>, FS: FragmentShader<V>
+	{
+		self.shaded.clear();
+		self.shaded.extend(vertices.iter().map(|vertex| vertex_shader.shade(vertex)));
+
+		let mut written = 0;
+		for tri in indices.chunks(3).filter(|tri| tri.len() == 3) {
+			let (screen_tri_1, screen_tri_2) = clip_triangle([self.shaded[tri[0]], self.shaded[tri[1]], self.shaded[tri[2]]]);
+
+			for screen_tri in screen_tri_1.iter().chain(screen_tri_2.iter()) {
+				if !self.cull.culls(screen_tri, self.front) {
+					written += draw_triangle(fb, screen_tri, |fragment| fragment_shader.shade(fragment));
+				}
+			}
+		}
+
+		written
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::cell::Cell;
+
+	//Moves every vertex by offset and passes its brightness on, counting how many it has shaded
+	struct Offset {
+		offset: Point3f,
+		shaded: Cell<usize>,
+	}
+
+	impl VertexShader<f32> for Offset {
+		type Vertex = (Point3f, f32);
+
+		fn shade(&self, vertex: &(Point3f, f32)) -> (Point3f, f32) {
+			self.shaded.set(self.shaded.get() + 1);
+			(vertex.0 + self.offset, vertex.1)
+		}
+	}
+
+	#[test]
+	fn draws_through_both_shaders() {
+		//A square two units in front of the camera, split on its diagonal
+		let vertices = [(Point3f::new(-0.1, -0.1, -2.0), 0.5), (Point3f::new(0.1, -0.1, -2.0), 0.5),
+						(Point3f::new(0.1, 0.1, -2.0), 0.5), (Point3f::new(-0.1, 0.1, -2.0), 0.5)];
+		let indices = [0, 1, 2, 0, 2, 3];
+		let vertex_shader = Offset { offset: Point3f::new(0.05, 0.0, 0.0), shaded: Cell::new(0) };
+
+		//Only the middle of the screen, where the square lands
+		let mut fb = Framebuffer::tile(880, 460, 160, 160);
+		let mut pipeline = Pipeline::new(CullMode::None, Winding::Clockwise);
+		let written = pipeline.draw(&mut fb, &vertices, &indices, &vertex_shader, &|fragment: &Fragment<f32>| {
+			LinearColor::new(fragment.attributes(), 0.0, 0.0)
+		});
+
+		//Shared corners are only shaded once
+		assert_eq!(vertex_shader.shaded.get(), 4);
+
+		let top_left = Point3f::new(-0.05, -0.1, -2.0).perspective_project();
+		let bottom_right = Point3f::new(0.15, 0.1, -2.0).perspective_project();
+		let (left, top) = (top_left.x().ceil() as usize, top_left.y().ceil() as usize);
+		let (right, bottom) = (bottom_right.x() as usize, bottom_right.y() as usize);
+		assert!(written >= (right - left) * (bottom - top));
+
+		//Drawn where the vertex shader moved the square to, and not where it started
+		for &(x, y) in [(left, top), (right - 1, bottom - 1), ((left + right) / 2, (top + bottom) / 2)].iter() {
+			assert_eq!(fb.pixel(x, y), LinearColor::new(0.5, 0.0, 0.0), "pixel {}, {}", x, y);
+		}
+		let start = Point3f::new(-0.1, 0.0, -2.0).perspective_project();
+		assert_eq!(fb.pixel(start.x() as usize + 1, start.y() as usize), LinearColor::black());
+	}
+
+	#[test]
+	fn shades_every_vertex_once_however_many_triangles_share_it() {
+		//A fan of six triangles round a center they all share
+		let mut vertices = vec![(Point3f::new(0.0, 0.0, -2.0), 1.0)];
+		for i in 0..6 {
+			let angle = i as f32 * ::std::f32::consts::PI / 3.0;
+			vertices.push((Point3f::new(angle.cos() * 0.1, angle.sin() * 0.1, -2.0), 0.0));
+		}
+		let indices: Vec<usize> = (0..6).flat_map(|i| vec![0, i + 1, (i + 1) % 6 + 1]).collect();
+		let vertex_shader = Offset { offset: Point3f::new(0.0, 0.0, 0.0), shaded: Cell::new(0) };
+
+		let mut fb = Framebuffer::tile(880, 460, 160, 160);
+		let mut pipeline = Pipeline::new(CullMode::None, Winding::Clockwise);
+		let fragment_shader = |fragment: &Fragment<f32>| LinearColor::new(fragment.attributes(), 0.0, 0.0);
+		assert!(pipeline.draw(&mut fb, &vertices, &indices, &vertex_shader, &fragment_shader) > 0);
+		assert_eq!(vertex_shader.shaded.get(), vertices.len());
+
+		//And again on every draw
+		pipeline.draw(&mut fb, &vertices, &indices, &vertex_shader, &fragment_shader);
+		assert_eq!(vertex_shader.shaded.get(), vertices.len() * 2);
+	}
+}
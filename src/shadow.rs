@@ -0,0 +1,173 @@
+use pointf::Point3f;
+use framebuffer::Framebuffer;
+use raster::ScreenVertex;
+use raster::draw_triangle_depth;
+use mesh::Mesh;
+
+//Depths are stored as closeness to the light, this keeps everything in front of the far side positive
+const DEPTH_MARGIN: f32 = 1.0;
+
+//How many texels either side of the lookup are averaged for percentage closer filtering
+const PCF_RADIUS: i32 = 1;
+
+/*
+Depth of the scene as seen from a directional light, rendered with the regular rasterizer
+through an orthographic projection that fits the whole mesh
+*/
+pub struct ShadowMap {
+	size: usize,
+
+	//Light space basis, forward points the way the light travels
+	right: Point3f,
+	up: Point3f,
+	forward: Point3f,
+
+	//Light space bounds of the mesh
+	min: Point3f,
+	max: Point3f,
+
+	depth: Framebuffer,
+}
+
+impl ShadowMap {
+	//Renders a size x size shadow map of every shadow casting triangle in mesh
+	pub fn render(mesh: &Mesh, direction: Point3f, size: usize) -> Option<ShadowMap> {
+		let forward = direction.normalize()?;
+
+		//Any up that isn't parallel to the light will do
+		let hint = if forward.y().abs() < 0.99 { Point3f::new(0.0, 1.0, 0.0) } else { Point3f::new(1.0, 0.0, 0.0) };
+		let right = hint.cross(forward).normalize()?;
+		let up = forward.cross(right);
+
+		let mut shadow_map = ShadowMap {
+			size: size,
+			right: right,
+			up: up,
+			forward: forward,
+			min: Point3f::new(0.0, 0.0, 0.0),
+			max: Point3f::new(0.0, 0.0, 0.0),
+			depth: Framebuffer::depth_only(size, size),
+		};
+
+		let mut bounds: Option<(Point3f, Point3f)> = None;
+		for index in 0..mesh.vertex_count() {
+			let p = shadow_map.light_space(mesh.position(index));
+			bounds = Some(match bounds {
+				Some((min, max)) => (Point3f::new(min.x().min(p.x()), min.y().min(p.y()), min.z().min(p.z())),
+									 Point3f::new(max.x().max(p.x()), max.y().max(p.y()), max.z().max(p.z()))),
+				None => (p, p),
+			});
+		}
+
+		let (min, max) = bounds?;
+		shadow_map.min = min;
+		shadow_map.max = max;
+
+		for tri in 0..mesh.triangle_count() {
+			if !mesh.casts_shadow(tri) {
+				continue;
+			}
+
+			let (i0, i1, i2) = mesh.triangle(tri);
+			let screen_tri = [shadow_map.project(mesh.position(i0)),
+							  shadow_map.project(mesh.position(i1)),
+							  shadow_map.project(mesh.position(i2))];
+
+			draw_triangle_depth(&mut shadow_map.depth, &screen_tri);
+		}
+
+		Some(shadow_map)
+	}
+
+	fn light_space(&self, position: Point3f) -> Point3f {
+		Point3f::new(position.dot(self.right), position.dot(self.up), position.dot(self.forward))
+	}
+
+	//Shadow map pixel coordinates and closeness to the light
+	fn texel(&self, position: Point3f) -> (f32, f32, f32) {
+		let p = self.light_space(position);
+		let scale = self.texels_per_unit();
+
+		((p.x() - self.min.x()) * scale,
+		 (p.y() - self.min.y()) * scale,
+		 self.max.z() - p.z() + DEPTH_MARGIN)
+	}
+
+	//Orthographic, so closeness is linear across the map. The rasterizer interpolates 1/w linearly
+	//and keeps the largest, so handing it w = 1/closeness stores closeness exactly
+	fn project(&self, position: Point3f) -> ScreenVertex<f32> {
+		let (x, y, closeness) = self.texel(position);
+
+		ScreenVertex::new(Point3f::new(x, y, -1.0 / closeness), 0.0)
+	}
+
+	//Square texels covering the larger side of the bounds
+	fn texels_per_unit(&self) -> f32 {
+		let extent = (self.max.x() - self.min.x()).max(self.max.y() - self.min.y()).max(0.001);
+
+		(self.size - 1) as f32 / extent
+	}
+
+	//How much of the light reaches position, 0 is fully shadowed and 1 fully lit.
+	//The lookup is pushed out along normal by a couple of texels so surfaces don't shadow themselves
+	pub fn visibility(&self, position: Point3f, normal: Point3f) -> f32 {
+		let texel_size = 1.0 / self.texels_per_unit();
+		let (x, y, closeness) = self.texel(position + normal * (texel_size * 2.0));
+		let bias = texel_size;
+
+		let (cx, cy) = (x.floor() as i32, y.floor() as i32);
+		let (mut lit, mut total) = (0, 0);
+		for dy in -PCF_RADIUS..(PCF_RADIUS + 1) {
+			for dx in -PCF_RADIUS..(PCF_RADIUS + 1) {
+				let (sx, sy) = (cx + dx, cy + dy);
+				total += 1;
+
+				//Outside the map nothing can be in the way
+				if sx < 0 || sy < 0 || sx >= self.size as i32 || sy >= self.size as i32 {
+					lit += 1;
+					continue;
+				}
+
+				if closeness + bias >= self.depth.depth(sx as usize, sy as usize) {
+					lit += 1;
+				}
+			}
+		}
+
+		lit as f32 / total as f32
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use triangle::Triangle3D;
+
+	//A horizontal square at height y, the world is y down so the light comes from smaller y
+	fn push_square(mesh: &mut Mesh, y: f32, half: f32) {
+		let corners = [Point3f::new(-half, y, -half), Point3f::new(half, y, -half),
+					   Point3f::new(half, y, half), Point3f::new(-half, y, half)];
+
+		mesh.push_triangle(&Triangle3D::new(corners[0], corners[1], corners[2]), 0);
+		mesh.push_triangle(&Triangle3D::new(corners[0], corners[2], corners[3]), 0);
+	}
+
+	#[test]
+	fn shadow_falls_inside_a_large_triangle() {
+		let mut mesh = Mesh::new();
+		push_square(&mut mesh, 10.0, 10.0);
+		push_square(&mut mesh, 5.0, 2.0);
+
+		let shadow_map = ShadowMap::render(&mesh, Point3f::new(0.0, 1.0, 0.0), 256).unwrap();
+		let up = Point3f::new(0.0, -1.0, 0.0);
+
+		//Every corner of the floor is lit, the middle of it is not
+		assert_eq!(shadow_map.visibility(Point3f::new(-10.0, 10.0, -10.0), up), 1.0);
+		assert_eq!(shadow_map.visibility(Point3f::new(10.0, 10.0, 10.0), up), 1.0);
+		assert_eq!(shadow_map.visibility(Point3f::new(0.0, 10.0, 0.0), up), 0.0);
+		assert_eq!(shadow_map.visibility(Point3f::new(6.0, 10.0, -6.0), up), 1.0);
+
+		//The occluder doesn't shadow itself
+		assert_eq!(shadow_map.visibility(Point3f::new(0.0, 5.0, 0.0), up), 1.0);
+	}
+}
@@ -0,0 +1,431 @@
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use pointf::Point2f;
+use color::LinearColor;
+
+//Pixels tested together in SIMD registers
+pub const LANES: usize = 4;
+
+//Most pixels cover_span() tests in one go, one bit each in Coverage::mask
+pub const SPAN: usize = 64;
+
+/*
+One edge of a triangle for testing coverage. A shared edge is always measured from the same end, so
+the two triangles on either side get exactly opposite values at every pixel and the fill rule can
+give the pixels right on it to just one of them
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct Edge {
+	//The end the edge is measured from
+	x: f32,
+	y: f32,
+
+	//To the other end, negated when that makes the inside of the triangle positive
+	dx: f32,
+	dy: f32,
+
+	//Pixels exactly on top and left edges are inside, on the others they're outside
+	inclusive: bool,
+}
+
+impl Edge {
+	//The edge from a to b of a triangle with the given signed area, as worked out by the rasterizer
+	pub fn new(a: Point2f, b: Point2f, area: f32) -> Edge {
+		let (from, to, sign) = if (a.x(), a.y()) <= (b.x(), b.y()) { (a, b, 1.0) } else { (b, a, -1.0) };
+		let sign = if area < 0.0 { -sign } else { sign };
+		let (dx, dy) = ((to.x() - from.x()) * sign, (to.y() - from.y()) * sign);
+
+		Edge {
+			x: from.x(),
+			y: from.y(),
+			dx: dx,
+			dy: dy,
+
+			//Inside is to the right of a left edge and below a flat top one
+			inclusive: dy < 0.0 || (dy == 0.0 && dx > 0.0),
+		}
+	}
+
+	//The part of the edge function that is the same along a row
+	pub fn row(&self, y: f32) -> f32 {
+		self.dx * (y - self.y)
+	}
+
+	//Whether a pixel with this edge function value is on the inside, row(y) - dy * (x - origin)
+	pub fn covers(&self, value: f32) -> bool {
+		value > 0.0 || (value == 0.0 && self.inclusive)
+	}
+}
+
+/*
+A triangle's edges along one row of a span, along with the barycentric weights at the first pixel
+and how much they change per pixel
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct SpanEdges {
+	pub weights: [f32; 3],
+	pub step: [f32; 3],
+
+	//1/w at the three corners
+	pub inv_w: [f32; 3],
+
+	//Where the first pixel is tested, with the sample offset
+	pub x: f32,
+
+	pub edges: [Edge; 3],
+
+	//Edge::row() for each edge at the row being tested
+	pub rows: [f32; 3],
+}
+
+impl SpanEdges {
+	//Weights are set for each row along with start_row()
+	pub fn new(edges: [Edge; 3], step: [f32; 3], inv_w: [f32; 3]) -> SpanEdges {
+		SpanEdges {
+			weights: [0.0; 3],
+			step: step,
+			inv_w: inv_w,
+			x: 0.0,
+			edges: edges,
+			rows: [0.0; 3],
+		}
+	}
+
+	//Moves to the start of a row at x, y
+	pub fn start_row(&mut self, x: f32, y: f32) {
+		self.x = x;
+		for i in 0..3 {
+			self.rows[i] = self.edges[i].row(y);
+		}
+	}
+
+	pub fn advance(&mut self, pixels: usize) {
+		for i in 0..3 {
+			self.weights[i] += self.step[i] * pixels as f32;
+		}
+		self.x += pixels as f32;
+	}
+}
+
+/*
+Which pixels of a span passed, along with their weights and interpolated 1/w
+*/
+pub struct Coverage {
+	pub mask: u64,
+	pub weights: [[f32; SPAN]; 3],
+	pub inv_w: [f32; SPAN],
+}
+
+impl Coverage {
+	pub fn new() -> Coverage {
+		Coverage {
+			mask: 0,
+			weights: [[0.0; SPAN]; 3],
+			inv_w: [0.0; SPAN],
+		}
+	}
+
+	pub fn weights(&self, pixel: usize) -> [f32; 3] {
+		[self.weights[0][pixel], self.weights[1][pixel], self.weights[2][pixel]]
+	}
+}
+
+impl Default for Coverage {
+	fn default() -> Coverage {
+		Coverage::new()
+	}
+}
+
+//Tests depth.len() pixels, at most SPAN, starting where edges is. Bit i of the mask is set when pixel
+//i is inside the triangle and closer than depth[i]. The weights of a pixel are worked out from
+//those of the first in its group of LANES, so every path gets exactly the same answers
+pub fn cover_span(edges: &SpanEdges, depth: &[f32], coverage: &mut Coverage) {
+	assert!(depth.len() <= SPAN);
+
+	#[cfg(target_arch = "x86_64")]
+	{
+		//SSE2 is part of x86_64 so there's nothing to detect
+		unsafe { cover_span_sse2(edges, depth, coverage) }
+	}
+
+	#[cfg(not(target_arch = "x86_64"))]
+	{
+		cover_span_scalar(edges, depth, coverage)
+	}
+}
+
+//One pixel at a time, for targets without SIMD and to check the SIMD version against
+pub fn cover_span_scalar(edges: &SpanEdges, depth: &[f32], coverage: &mut Coverage) {
+	let mut group = edges.weights;
+	let group_step = [edges.step[0] * LANES as f32, edges.step[1] * LANES as f32, edges.step[2] * LANES as f32];
+	coverage.mask = 0;
+
+	for start in (0..depth.len()).step_by(LANES) {
+		for lane in 0..LANES.min(depth.len() - start) {
+			let pixel = start + lane;
+			let b = [group[0] + edges.step[0] * lane as f32,
+					 group[1] + edges.step[1] * lane as f32,
+					 group[2] + edges.step[2] * lane as f32];
+			let inv_w = b[0] * edges.inv_w[0] + b[1] * edges.inv_w[1] + b[2] * edges.inv_w[2];
+
+			for i in 0..3 {
+				coverage.weights[i][pixel] = b[i];
+			}
+			coverage.inv_w[pixel] = inv_w;
+
+			//Each edge is tested from x like the SIMD version so shared edges come out exactly opposite
+			let x = edges.x + pixel as f32;
+			let inside = (0..3).all(|i| {
+				let edge = &edges.edges[i];
+				edge.covers(edges.rows[i] - edge.dy * (x - edge.x))
+			});
+			if inside && inv_w > depth[pixel] {
+				coverage.mask |= 1 << pixel;
+			}
+		}
+
+		for i in 0..3 {
+			group[i] += group_step[i];
+		}
+	}
+}
+
+#[cfg(target_arch = "x86_64")]
+unsafe fn cover_span_sse2(edges: &SpanEdges, depth: &[f32], coverage: &mut Coverage) {
+	let lanes = _mm_set_ps(3.0, 2.0, 1.0, 0.0);
+	let zero = _mm_setzero_ps();
+
+	//Each group starts from a broadcast of the scalar weights so it rounds like the scalar version
+	let mut group = [_mm_set1_ps(edges.weights[0]), _mm_set1_ps(edges.weights[1]), _mm_set1_ps(edges.weights[2])];
+	let group_step = [_mm_set1_ps(edges.step[0] * LANES as f32),
+					  _mm_set1_ps(edges.step[1] * LANES as f32),
+					  _mm_set1_ps(edges.step[2] * LANES as f32)];
+	let lane_step = [_mm_mul_ps(_mm_set1_ps(edges.step[0]), lanes),
+					 _mm_mul_ps(_mm_set1_ps(edges.step[1]), lanes),
+					 _mm_mul_ps(_mm_set1_ps(edges.step[2]), lanes)];
+	let corner_inv_w = [_mm_set1_ps(edges.inv_w[0]), _mm_set1_ps(edges.inv_w[1]), _mm_set1_ps(edges.inv_w[2])];
+
+	let rows = [_mm_set1_ps(edges.rows[0]), _mm_set1_ps(edges.rows[1]), _mm_set1_ps(edges.rows[2])];
+	let slopes = [_mm_set1_ps(edges.edges[0].dy), _mm_set1_ps(edges.edges[1].dy), _mm_set1_ps(edges.edges[2].dy)];
+	let origins = [_mm_set1_ps(edges.edges[0].x), _mm_set1_ps(edges.edges[1].x), _mm_set1_ps(edges.edges[2].x)];
+	let inclusive = [inclusive_mask(&edges.edges[0]), inclusive_mask(&edges.edges[1]), inclusive_mask(&edges.edges[2])];
+	let mut x = _mm_add_ps(_mm_set1_ps(edges.x), lanes);
+	let group_x = _mm_set1_ps(LANES as f32);
+
+	let mut mask = 0u64;
+	let mut start = 0;
+	while start < depth.len() {
+		//The last group is padded with a depth nothing can beat
+		let count = LANES.min(depth.len() - start);
+		let stored = if count == LANES {
+			_mm_loadu_ps(depth.as_ptr().add(start))
+		} else {
+			let mut padded = [f32::INFINITY; LANES];
+			padded[..count].copy_from_slice(&depth[start..]);
+			_mm_loadu_ps(padded.as_ptr())
+		};
+
+		let b0 = _mm_add_ps(group[0], lane_step[0]);
+		let b1 = _mm_add_ps(group[1], lane_step[1]);
+		let b2 = _mm_add_ps(group[2], lane_step[2]);
+
+		let mut inside = _mm_castsi128_ps(_mm_set1_epi32(-1));
+		for i in 0..3 {
+			let value = _mm_sub_ps(rows[i], _mm_mul_ps(slopes[i], _mm_sub_ps(x, origins[i])));
+			let on_edge = _mm_and_ps(_mm_cmpeq_ps(value, zero), inclusive[i]);
+			inside = _mm_and_ps(inside, _mm_or_ps(_mm_cmpgt_ps(value, zero), on_edge));
+		}
+		let inv_w = _mm_add_ps(_mm_add_ps(_mm_mul_ps(b0, corner_inv_w[0]), _mm_mul_ps(b1, corner_inv_w[1])),
+							   _mm_mul_ps(b2, corner_inv_w[2]));
+		let closer = _mm_cmpgt_ps(inv_w, stored);
+
+		//Coverage has room for a whole group past SPAN - LANES since SPAN is a multiple of LANES
+		_mm_storeu_ps(coverage.weights[0].as_mut_ptr().add(start), b0);
+		_mm_storeu_ps(coverage.weights[1].as_mut_ptr().add(start), b1);
+		_mm_storeu_ps(coverage.weights[2].as_mut_ptr().add(start), b2);
+		_mm_storeu_ps(coverage.inv_w.as_mut_ptr().add(start), inv_w);
+
+		mask |= (_mm_movemask_ps(_mm_and_ps(inside, closer)) as u64) << start;
+
+		for i in 0..3 {
+			group[i] = _mm_add_ps(group[i], group_step[i]);
+		}
+		x = _mm_add_ps(x, group_x);
+		start += LANES;
+	}
+
+	coverage.mask = mask;
+}
+
+//All ones in every lane for edges that own the pixels on them
+#[cfg(target_arch = "x86_64")]
+unsafe fn inclusive_mask(edge: &Edge) -> __m128 {
+	_mm_castsi128_ps(_mm_set1_epi32(if edge.inclusive { -1 } else { 0 }))
+}
+
+//Sets every value in a span, LANES at a time
+pub fn fill(span: &mut [f32], value: f32) {
+	#[cfg(target_arch = "x86_64")]
+	{
+		unsafe { fill_sse2(span, value) }
+	}
+
+	#[cfg(not(target_arch = "x86_64"))]
+	{
+		fill_scalar(span, value)
+	}
+}
+
+pub fn fill_scalar(span: &mut [f32], value: f32) {
+	for v in span.iter_mut() {
+		*v = value;
+	}
+}
+
+#[cfg(target_arch = "x86_64")]
+unsafe fn fill_sse2(span: &mut [f32], value: f32) {
+	let wide = _mm_set1_ps(value);
+	let mut chunks = span.chunks_exact_mut(LANES);
+
+	for chunk in &mut chunks {
+		_mm_storeu_ps(chunk.as_mut_ptr(), wide);
+	}
+	fill_scalar(chunks.into_remainder(), value);
+}
+
+//Copies the colors of the pixels with their bit set in mask, whole groups of LANES at a time
+pub fn store_colors(dst: &mut [LinearColor], src: &[LinearColor], mask: u64) {
+	assert!(dst.len() == src.len() && dst.len() <= SPAN);
+
+	#[cfg(target_arch = "x86_64")]
+	{
+		unsafe { store_colors_sse2(dst, src, mask) }
+	}
+
+	#[cfg(not(target_arch = "x86_64"))]
+	{
+		store_colors_scalar(dst, src, mask)
+	}
+}
+
+pub fn store_colors_scalar(dst: &mut [LinearColor], src: &[LinearColor], mut mask: u64) {
+	while mask != 0 {
+		let pixel = mask.trailing_zeros() as usize;
+		mask &= mask - 1;
+
+		dst[pixel] = src[pixel];
+	}
+}
+
+#[cfg(target_arch = "x86_64")]
+unsafe fn store_colors_sse2(dst: &mut [LinearColor], src: &[LinearColor], mask: u64) {
+	const GROUP: u64 = (1 << LANES) - 1;
+
+	let mut start = 0;
+	while start < dst.len() {
+		let group = (mask >> start) & GROUP;
+
+		//LANES colors are three registers of floats
+		if group == GROUP && start + LANES <= dst.len() {
+			let from = src.as_ptr().add(start) as *const f32;
+			let to = dst.as_mut_ptr().add(start) as *mut f32;
+			for i in 0..3 {
+				_mm_storeu_ps(to.add(i * LANES), _mm_loadu_ps(from.add(i * LANES)));
+			}
+		} else if group != 0 {
+			let end = (start + LANES).min(dst.len());
+			store_colors_scalar(&mut dst[start..end], &src[start..end], group);
+		}
+		start += LANES;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	//Small deterministic generator so failures can be reproduced
+	struct Random(u32);
+
+	impl Random {
+		fn next(&mut self) -> f32 {
+			self.0 = self.0.wrapping_mul(1664525).wrapping_add(1013904223);
+			(self.0 >> 8) as f32 / (1 << 24) as f32
+		}
+
+		fn point(&mut self) -> Point2f {
+			//Snapped to quarter pixels now and then so some pixels land right on an edge
+			let (x, y) = (self.next() * 80.0 - 8.0, self.next() * 20.0 - 4.0);
+			if self.next() < 0.5 { Point2f::new((x * 4.0).round() / 4.0, (y * 4.0).round() / 4.0) } else { Point2f::new(x, y) }
+		}
+	}
+
+	fn edge(a: Point2f, b: Point2f, p: Point2f) -> f32 {
+		(b.x() - a.x()) * (p.y() - a.y()) - (b.y() - a.y()) * (p.x() - a.x())
+	}
+
+	#[test]
+	fn cover_span_matches_scalar() {
+		let mut random = Random(1);
+		let (mut simd, mut scalar) = (Coverage::new(), Coverage::new());
+
+		for _ in 0..500 {
+			let p = [random.point(), random.point(), random.point()];
+			let area = edge(p[0], p[1], p[2]);
+			if area == 0.0 {
+				continue;
+			}
+
+			let edges = [Edge::new(p[1], p[2], area), Edge::new(p[2], p[0], area), Edge::new(p[0], p[1], area)];
+			let step = [(p[1].y() - p[2].y()) / area, (p[2].y() - p[0].y()) / area, (p[0].y() - p[1].y()) / area];
+			let mut span = SpanEdges::new(edges, step, [random.next(), random.next(), random.next()]);
+
+			for row in 0..16 {
+				let start = Point2f::new(-4.5, row as f32 - 3.5);
+				span.start_row(start.x(), start.y());
+				span.weights = [edge(p[1], p[2], start) / area, edge(p[2], p[0], start) / area, edge(p[0], p[1], start) / area];
+
+				let depth: Vec<f32> = (0..SPAN).map(|_| random.next() * 0.5).collect();
+				let count = 1 + (random.next() * SPAN as f32) as usize % SPAN;
+
+				cover_span(&span, &depth[..count], &mut simd);
+				cover_span_scalar(&span, &depth[..count], &mut scalar);
+
+				assert_eq!(simd.mask, scalar.mask);
+				for pixel in 0..count {
+					assert_eq!(simd.weights(pixel), scalar.weights(pixel));
+					assert_eq!(simd.inv_w[pixel], scalar.inv_w[pixel]);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn store_colors_matches_scalar() {
+		let mut random = Random(2);
+		let src: Vec<LinearColor> = (0..SPAN).map(|_| LinearColor::new(random.next(), random.next(), random.next())).collect();
+
+		for &count in [1, 3, 4, 7, 33, SPAN].iter() {
+			for &mask in [0, !0, 0x0f0f_f0f0_1234_5678, 0xffff_ffff_0000_fff1, 0xaaaa_aaaa_aaaa_aaaa].iter() {
+				let mask = if count == SPAN { mask } else { mask & ((1 << count) - 1) };
+				let (mut simd, mut scalar) = (vec![LinearColor::black(); count], vec![LinearColor::black(); count]);
+
+				store_colors(&mut simd, &src[..count], mask);
+				store_colors_scalar(&mut scalar, &src[..count], mask);
+				assert_eq!(simd, scalar);
+			}
+		}
+	}
+
+	#[test]
+	fn fill_matches_scalar() {
+		for count in 0..11 {
+			let (mut simd, mut scalar) = (vec![0.0; count], vec![0.0; count]);
+
+			fill(&mut simd, 0.25);
+			fill_scalar(&mut scalar, 0.25);
+			assert_eq!(simd, scalar);
+		}
+	}
+}
@@ -0,0 +1,104 @@
+use std::fmt;
+use std::time::Duration;
+
+use color::Color;
+use framebuffer::Framebuffer;
+use font::{draw_text_scaled, text_size, darken_box};
+
+//Space around the HUD's text, before scaling
+const HUD_MARGIN: usize = 4;
+
+/*
+What went into drawing the last frame and how long each stage took
+*/
+#[derive(Copy, Clone, Default, Debug)]
+pub struct FrameStats {
+	//Mesh triangles looked at
+	pub submitted: usize,
+	//Mesh triangles dropped because they were behind the near plane or beyond the fog
+	pub clipped: usize,
+	//Screen triangles dropped for facing the wrong way
+	pub culled: usize,
+	//Screen triangles handed to the rasterizer
+	pub rasterized: usize,
+
+	//Pixels shaded and written, how many pixels ended up with an opaque surface in them and how
+	//many pixels the frame has
+	pub pixels_written: usize,
+	pub covered_pixels: usize,
+	pub screen_pixels: usize,
+
+	//Moving the mesh in front of the camera and lighting its vertices
+	pub transform_time: Duration,
+	//Clipping, culling and queuing triangles
+	pub setup_time: Duration,
+	//Filling triangles
+	pub raster_time: Duration,
+	//Tone mapping, post-processing and quantizing the frame for display, and drawing any wireframe over it
+	pub resolve_time: Duration,
+}
+
+impl FrameStats {
+	//How many times each covered pixel was written on average, 1 means nothing was drawn over.
+	//Pixels left showing the background don't count, translucent surfaces add to the writes
+	pub fn overdraw(&self) -> f32 {
+		self.pixels_written as f32 / self.covered_pixels.max(1) as f32
+	}
+
+	//How much of the frame has something drawn in it, from 0 to 1
+	pub fn coverage(&self) -> f32 {
+		self.covered_pixels as f32 / self.screen_pixels.max(1) as f32
+	}
+
+	pub fn total_time(&self) -> Duration {
+		self.transform_time + self.setup_time + self.raster_time + self.resolve_time
+	}
+
+	//Writes the stats over a darkened box in the top left corner of the framebuffer's resolved pixels
+	pub fn draw_hud(&self, framebuffer: &mut Framebuffer, scale: usize) {
+		let text = self.to_string();
+		let (width, height) = text_size(&text, scale);
+		let margin = HUD_MARGIN * scale;
+
+		darken_box(framebuffer, 0, 0, width + margin * 2, height + margin * 2);
+		draw_text_scaled(framebuffer, margin, margin, &text, Color::new(255, 255, 255), scale);
+	}
+}
+
+fn milliseconds(time: Duration) -> f64 {
+	time.as_secs_f64() * 1000.0
+}
+
+impl fmt::Display for FrameStats {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let total = self.total_time();
+		let fps = if total > Duration::from_secs(0) { 1.0 / total.as_secs_f64() } else { 0.0 };
+
+		writeln!(f, "frame     {:7.2} ms {:6.1} fps", milliseconds(total), fps)?;
+		writeln!(f, "transform {:7.2} ms", milliseconds(self.transform_time))?;
+		writeln!(f, "setup     {:7.2} ms", milliseconds(self.setup_time))?;
+		writeln!(f, "raster    {:7.2} ms", milliseconds(self.raster_time))?;
+		writeln!(f, "resolve   {:7.2} ms", milliseconds(self.resolve_time))?;
+		writeln!(f, "triangles {} submitted", self.submitted)?;
+		writeln!(f, "          {} clipped", self.clipped)?;
+		writeln!(f, "          {} culled", self.culled)?;
+		writeln!(f, "          {} rasterized", self.rasterized)?;
+		writeln!(f, "pixels    {} written", self.pixels_written)?;
+		writeln!(f, "          {} covered, {:.0}%", self.covered_pixels, self.coverage() * 100.0)?;
+		write!(f, "overdraw  {:.2}x", self.overdraw())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn overdraw_is_per_covered_pixel() {
+		let stats = FrameStats { pixels_written: 300, covered_pixels: 200, screen_pixels: 1000, ..FrameStats::default() };
+
+		assert_eq!(stats.overdraw(), 1.5);
+		assert_eq!(stats.coverage(), 0.2);
+		assert_eq!(FrameStats::default().overdraw(), 0.0);
+	}
+}
@@ -0,0 +1,675 @@
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use super::png;
+
+use pointf::Point2f;
+use color::Color;
+use color::LinearColor;
+use color::srgb_decode_table;
+use palette::Palette;
+
+//Widest or tallest image the BMP and TGA loaders take, past this a header is more likely broken
+const MAX_IMAGE_SIZE: usize = 16384;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum WrapMode {
+	Repeat,
+	Clamp,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FilterMode {
+	Nearest,
+	Bilinear,
+	Trilinear,
+}
+
+impl FilterMode {
+	//Cycles through the modes, for switching at runtime
+	pub fn next(&self) -> FilterMode {
+		match *self {
+			FilterMode::Nearest => FilterMode::Bilinear,
+			FilterMode::Bilinear => FilterMode::Trilinear,
+			FilterMode::Trilinear => FilterMode::Nearest,
+		}
+	}
+}
+
+/*
+One level of a mip chain, texels are in linear light so filtering averages light rather than sRGB values
+*/
+struct MipLevel {
+	width: usize,
+	height: usize,
+	texels: Vec<LinearColor>,
+}
+
+impl MipLevel {
+	//Averages each 2x2 block into a level half the size
+	fn downsample(&self) -> MipLevel {
+		let width = (self.width / 2).max(1);
+		let height = (self.height / 2).max(1);
+
+		let mut texels = Vec::with_capacity(width * height);
+		for y in 0..height {
+			for x in 0..width {
+				let (x0, y0) = ((x * 2).min(self.width - 1), (y * 2).min(self.height - 1));
+				let (x1, y1) = ((x * 2 + 1).min(self.width - 1), (y * 2 + 1).min(self.height - 1));
+
+				let block = [self.texels[x0 + y0 * self.width], self.texels[x1 + y0 * self.width],
+							 self.texels[x0 + y1 * self.width], self.texels[x1 + y1 * self.width]];
+
+				texels.push((block[0] + block[1] + block[2] + block[3]) * 0.25);
+			}
+		}
+
+		MipLevel {
+			width: width,
+			height: height,
+			texels: texels,
+		}
+	}
+}
+
+/*
+An sRGB image decoded to linear light with its mip chain that can be sampled with texture coordinates, (0,0) is the top left texel
+*/
+pub struct Texture {
+	levels: Vec<MipLevel>,
+
+	wrap: WrapMode,
+}
+
+impl Texture {
+	pub fn new(width: usize, height: usize, texels: Vec<Color>) -> Texture {
+		assert_eq!(width * height, texels.len());
+
+		let decode = srgb_decode_table();
+		let texels = texels.iter()
+			.map(|c| LinearColor::new(decode[c.r() as usize], decode[c.g() as usize], decode[c.b() as usize]))
+			.collect();
+
+		let mut levels = vec![MipLevel {
+			width: width,
+			height: height,
+			texels: texels,
+		}];
+
+		while levels[levels.len() - 1].width > 1 || levels[levels.len() - 1].height > 1 {
+			let next = levels[levels.len() - 1].downsample();
+			levels.push(next);
+		}
+
+		Texture {
+			levels: levels,
+			wrap: WrapMode::Repeat,
+		}
+	}
+
+	//Loads a PNG, BMP or TGA file, picked by the file extension
+	pub fn load(path: &Path) -> io::Result<Texture> {
+		let extension = path.extension()
+			.and_then(|e| e.to_str())
+			.map(|e| e.to_lowercase())
+			.unwrap_or(String::new());
+
+		match extension.as_str() {
+			"png" => load_png(path),
+			"bmp" => decode_bmp(&read_file(path)?),
+			"tga" => decode_tga(&read_file(path)?),
+			_ => Err(invalid_data(&format!("unsupported texture format {}", path.display()))),
+		}
+	}
+
+	//Samples the texture, duv_dx and duv_dy are how much uv changes per pixel and pick the mip level
+	pub fn sample(&self, uv: Point2f, duv_dx: Point2f, duv_dy: Point2f, filter: FilterMode) -> LinearColor {
+		let lod = self.lod(duv_dx, duv_dy);
+
+		match filter {
+			FilterMode::Nearest => self.nearest(lod.round() as usize, uv),
+			FilterMode::Bilinear => self.bilinear(lod.round() as usize, uv),
+			FilterMode::Trilinear => {
+				let level = lod.floor() as usize;
+				if level + 1 >= self.levels.len() {
+					return self.bilinear(level, uv);
+				}
+
+				self.bilinear(level, uv).lerp(self.bilinear(level + 1, uv), lod - level as f32)
+			}
+		}
+	}
+
+	fn lod(&self, duv_dx: Point2f, duv_dy: Point2f) -> f32 {
+		mip_lod(self.levels[0].width, self.levels[0].height, self.levels.len(), duv_dx, duv_dy)
+	}
+
+	fn nearest(&self, level: usize, uv: Point2f) -> LinearColor {
+		let mip = &self.levels[level];
+		let x = (uv.x() * mip.width as f32).floor() as i32;
+		let y = (uv.y() * mip.height as f32).floor() as i32;
+
+		self.texel(level, x, y)
+	}
+
+	//Blends the four texels around uv, texel centers sit half a texel in
+	fn bilinear(&self, level: usize, uv: Point2f) -> LinearColor {
+		let mip = &self.levels[level];
+		let u = uv.x() * mip.width as f32 - 0.5;
+		let v = uv.y() * mip.height as f32 - 0.5;
+
+		let (x, y) = (u.floor(), v.floor());
+		let (fx, fy) = (u - x, v - y);
+		let (x, y) = (x as i32, y as i32);
+
+		let top = self.texel(level, x, y).lerp(self.texel(level, x + 1, y), fx);
+		let bottom = self.texel(level, x, y + 1).lerp(self.texel(level, x + 1, y + 1), fx);
+
+		top.lerp(bottom, fy)
+	}
+
+	//Fetches a single texel, coordinates outside the texture are wrapped
+	pub fn texel(&self, level: usize, x: i32, y: i32) -> LinearColor {
+		let mip = &self.levels[level];
+		let (x, y) = match self.wrap {
+			WrapMode::Repeat => (wrap_repeat(x, mip.width), wrap_repeat(y, mip.height)),
+			WrapMode::Clamp => (wrap_clamp(x, mip.width), wrap_clamp(y, mip.height)),
+		};
+
+		mip.texels[x + y * mip.width]
+	}
+
+	pub fn set_wrap(&mut self, wrap: WrapMode) {
+		self.wrap = wrap;
+	}
+
+	pub fn wrap(&self) -> WrapMode {
+		self.wrap
+	}
+
+	pub fn width(&self) -> usize {
+		self.levels[0].width
+	}
+
+	pub fn height(&self) -> usize {
+		self.levels[0].height
+	}
+
+	//The texture with every mip level quantized to palette indices, for indexed color rendering
+	pub fn to_indexed(&self, palette: &Palette) -> IndexedTexture {
+		let levels = self.levels.iter().map(|mip| {
+			IndexedLevel {
+				width: mip.width,
+				height: mip.height,
+				indices: mip.texels.iter().map(|t| palette.nearest(t.to_srgb())).collect(),
+			}
+		}).collect();
+
+		IndexedTexture {
+			levels: levels,
+			wrap: self.wrap,
+		}
+	}
+}
+
+struct IndexedLevel {
+	width: usize,
+	height: usize,
+	indices: Vec<u8>,
+}
+
+/*
+A texture stored as palette indices, a byte per texel. Indices can't be blended so it is only ever
+sampled nearest, the mip level is still picked per pixel to keep distant walls from sparkling
+*/
+pub struct IndexedTexture {
+	levels: Vec<IndexedLevel>,
+
+	wrap: WrapMode,
+}
+
+impl IndexedTexture {
+	pub fn sample(&self, uv: Point2f, duv_dx: Point2f, duv_dy: Point2f) -> u8 {
+		let level = mip_lod(self.levels[0].width, self.levels[0].height, self.levels.len(), duv_dx, duv_dy).round() as usize;
+		let mip = &self.levels[level];
+
+		let x = (uv.x() * mip.width as f32).floor() as i32;
+		let y = (uv.y() * mip.height as f32).floor() as i32;
+		let (x, y) = match self.wrap {
+			WrapMode::Repeat => (wrap_repeat(x, mip.width), wrap_repeat(y, mip.height)),
+			WrapMode::Clamp => (wrap_clamp(x, mip.width), wrap_clamp(y, mip.height)),
+		};
+
+		mip.indices[x + y * mip.width]
+	}
+}
+
+//Mip level from the largest texel footprint of a pixel, 0 when magnifying
+fn mip_lod(width: usize, height: usize, levels: usize, duv_dx: Point2f, duv_dy: Point2f) -> f32 {
+	let (width, height) = (width as f32, height as f32);
+
+	let dx = (duv_dx.x() * width) * (duv_dx.x() * width) + (duv_dx.y() * height) * (duv_dx.y() * height);
+	let dy = (duv_dy.x() * width) * (duv_dy.x() * width) + (duv_dy.y() * height) * (duv_dy.y() * height);
+
+	let lod = 0.5 * dx.max(dy).log2();
+	if !(lod > 0.0) {
+		return 0.0;
+	}
+
+	lod.min((levels - 1) as f32)
+}
+
+fn wrap_repeat(i: i32, size: usize) -> usize {
+	let size = size as i32;
+	(((i % size) + size) % size) as usize
+}
+
+fn wrap_clamp(i: i32, size: usize) -> usize {
+	i.max(0).min(size as i32 - 1) as usize
+}
+
+//For files that aren't laid out the way their format says, shared with the palette and post-process loaders
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+	let mut data = Vec::new();
+	File::open(path)?.read_to_end(&mut data)?;
+
+	Ok(data)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> io::Result<u16> {
+	if offset + 2 > data.len() {
+		return Err(invalid_data("unexpected end of file"));
+	}
+
+	Ok(data[offset] as u16 | (data[offset + 1] as u16) << 8)
+}
+
+//Rejects empty images and ones too big to be anything but a broken header
+fn check_size(width: usize, height: usize) -> io::Result<()> {
+	if width == 0 || height == 0 {
+		return Err(invalid_data("image has no pixels"));
+	}
+	if width > MAX_IMAGE_SIZE || height > MAX_IMAGE_SIZE {
+		return Err(invalid_data(&format!("{}x{} is too big for a texture", width, height)));
+	}
+
+	Ok(())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> io::Result<u32> {
+	Ok(read_u16(data, offset)? as u32 | (read_u16(data, offset + 2)? as u32) << 16)
+}
+
+fn load_png(path: &Path) -> io::Result<Texture> {
+	let mut decoder = png::Decoder::new(File::open(path)?);
+	decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+	let mut reader = decoder.read_info().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+	let mut buf = vec![0; reader.output_buffer_size()];
+	let info = reader.next_frame(&mut buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+	let channels = match info.color_type {
+		png::ColorType::Grayscale => 1,
+		png::ColorType::GrayscaleAlpha => 2,
+		png::ColorType::Rgb => 3,
+		png::ColorType::Rgba => 4,
+		png::ColorType::Indexed => return Err(invalid_data("indexed PNG was not expanded")),
+	};
+
+	let (width, height) = (info.width as usize, info.height as usize);
+	let mut texels = Vec::with_capacity(width * height);
+
+	for y in 0..height {
+		let row = &buf[y * info.line_size..];
+		for x in 0..width {
+			let p = &row[x * channels..];
+			texels.push(match channels {
+				1 | 2 => Color::new(p[0], p[0], p[0]),
+				_ => Color::new(p[0], p[1], p[2]),
+			});
+		}
+	}
+
+	Ok(Texture::new(width, height, texels))
+}
+
+//Uncompressed 8, 24 and 32 bit BMPs
+fn decode_bmp(data: &[u8]) -> io::Result<Texture> {
+	if data.len() < 54 || &data[0..2] != b"BM" {
+		return Err(invalid_data("not a BMP file"));
+	}
+
+	let pixel_offset = read_u32(data, 10)? as usize;
+	let header_size = read_u32(data, 14)? as usize;
+	let width = read_u32(data, 18)? as i32;
+	let height = read_u32(data, 22)? as i32;
+	let bpp = read_u16(data, 28)?;
+	let compression = read_u32(data, 30)?;
+
+	//Bitfields are accepted for 32 bit images as long as they use the usual BGRA layout
+	if compression != 0 && !(compression == 3 && bpp == 32) {
+		return Err(invalid_data("compressed BMPs are not supported"));
+	}
+
+	//Rows are stored bottom up unless the height is negative
+	let bottom_up = height > 0;
+	let (width, height) = (width.max(0) as usize, height.unsigned_abs() as usize);
+	check_size(width, height)?;
+
+	let mut palette: Vec<Color> = Vec::new();
+	if bpp == 8 {
+		let mut colors = read_u32(data, 46)? as usize;
+		if colors == 0 {
+			colors = 256;
+		}
+
+		let palette_offset = 14 + header_size;
+		for i in 0..colors {
+			let p = palette_offset + i * 4;
+			if p + 3 > data.len() {
+				return Err(invalid_data("unexpected end of file"));
+			}
+			palette.push(Color::new(data[p + 2], data[p + 1], data[p]));
+		}
+	}
+
+	let bytes_per_pixel = match bpp {
+		8 => 1,
+		24 => 3,
+		32 => 4,
+		_ => return Err(invalid_data(&format!("unsupported BMP bit depth {}", bpp))),
+	};
+
+	//Each row is padded out to 4 bytes
+	let row_size = (width * bytes_per_pixel + 3) & !3;
+	match row_size.checked_mul(height).and_then(|size| size.checked_add(pixel_offset)) {
+		Some(end) if end <= data.len() => {}
+		_ => return Err(invalid_data("unexpected end of file")),
+	}
+
+	let mut texels = Vec::with_capacity(width * height);
+	for y in 0..height {
+		let row = if bottom_up { height - 1 - y } else { y };
+		let row = &data[pixel_offset + row * row_size..];
+
+		for x in 0..width {
+			let p = &row[x * bytes_per_pixel..];
+			texels.push(match bpp {
+				8 => match palette.get(p[0] as usize) {
+					Some(&color) => color,
+					None => return Err(invalid_data("BMP palette index out of range")),
+				},
+				_ => Color::new(p[2], p[1], p[0]),
+			});
+		}
+	}
+
+	Ok(Texture::new(width, height, texels))
+}
+
+//Uncompressed and RLE truecolor/grayscale TGAs
+fn decode_tga(data: &[u8]) -> io::Result<Texture> {
+	if data.len() < 18 {
+		return Err(invalid_data("not a TGA file"));
+	}
+
+	let id_length = data[0] as usize;
+	let color_map_type = data[1];
+	let image_type = data[2];
+	let width = read_u16(data, 12)? as usize;
+	let height = read_u16(data, 14)? as usize;
+	let bpp = data[16];
+	let top_down = data[17] & 0x20 != 0;
+
+	if color_map_type != 0 {
+		return Err(invalid_data("color mapped TGAs are not supported"));
+	}
+
+	check_size(width, height)?;
+
+	let rle = match image_type {
+		2 | 3 => false,
+		10 | 11 => true,
+		_ => return Err(invalid_data(&format!("unsupported TGA image type {}", image_type))),
+	};
+
+	let bytes_per_pixel = match bpp {
+		8 => 1,
+		24 => 3,
+		32 => 4,
+		_ => return Err(invalid_data(&format!("unsupported TGA bit depth {}", bpp))),
+	};
+
+	let decode = |p: &[u8]| -> Color {
+		match bytes_per_pixel {
+			1 => Color::new(p[0], p[0], p[0]),
+			_ => Color::new(p[2], p[1], p[0]),
+		}
+	};
+
+	//The smallest the pixel data could be, RLE at best repeats one pixel per 128
+	let pixel_count = width * height;
+	let smallest = if rle {
+		((pixel_count + 127) / 128).checked_mul(1 + bytes_per_pixel)
+	} else {
+		pixel_count.checked_mul(bytes_per_pixel)
+	};
+	match smallest.and_then(|size| size.checked_add(18 + id_length)) {
+		Some(end) if end <= data.len() => {}
+		_ => return Err(invalid_data("unexpected end of file")),
+	}
+
+	let mut pixels: Vec<Color> = Vec::with_capacity(pixel_count);
+	let mut offset = 18 + id_length;
+
+	while pixels.len() < width * height {
+		let (count, repeat) = if rle {
+			if offset >= data.len() {
+				return Err(invalid_data("unexpected end of file"));
+			}
+			let packet = data[offset];
+			offset += 1;
+
+			((packet & 0x7f) as usize + 1, packet & 0x80 != 0)
+		} else {
+			(width * height, false)
+		};
+
+		let needed = if repeat { bytes_per_pixel } else { bytes_per_pixel * count };
+		if offset + needed > data.len() {
+			return Err(invalid_data("unexpected end of file"));
+		}
+
+		for i in 0..count {
+			let p = if repeat { offset } else { offset + i * bytes_per_pixel };
+			pixels.push(decode(&data[p..]));
+		}
+		offset += needed;
+	}
+	pixels.truncate(width * height);
+
+	//Rows are stored bottom up unless the descriptor says otherwise
+	let mut texels = Vec::with_capacity(width * height);
+	for y in 0..height {
+		let row = if top_down { y } else { height - 1 - y };
+		texels.extend_from_slice(&pixels[row * width..(row + 1) * width]);
+	}
+
+	Ok(Texture::new(width, height, texels))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn texels(texture: &Texture) -> Vec<LinearColor> {
+		let mut texels = Vec::new();
+		for y in 0..texture.height() as i32 {
+			for x in 0..texture.width() as i32 {
+				texels.push(texture.texel(0, x, y));
+			}
+		}
+		texels
+	}
+
+	fn push_u16(data: &mut Vec<u8>, value: u16) {
+		data.push(value as u8);
+		data.push((value >> 8) as u8);
+	}
+
+	fn push_u32(data: &mut Vec<u8>, value: u32) {
+		push_u16(data, value as u16);
+		push_u16(data, (value >> 16) as u16);
+	}
+
+	//A BITMAPINFOHEADER BMP, rows have to be padded by the caller
+	fn bmp(width: i32, height: i32, bpp: u16, palette: &[Color], pixels: &[u8]) -> Vec<u8> {
+		let pixel_offset = 54 + palette.len() as u32 * 4;
+
+		let mut data = b"BM".to_vec();
+		push_u32(&mut data, pixel_offset + pixels.len() as u32);
+		push_u32(&mut data, 0);
+		push_u32(&mut data, pixel_offset);
+		push_u32(&mut data, 40);
+		push_u32(&mut data, width as u32);
+		push_u32(&mut data, height as u32);
+		push_u16(&mut data, 1);
+		push_u16(&mut data, bpp);
+		push_u32(&mut data, 0);
+		push_u32(&mut data, pixels.len() as u32);
+		push_u32(&mut data, 2835);
+		push_u32(&mut data, 2835);
+		push_u32(&mut data, palette.len() as u32);
+		push_u32(&mut data, 0);
+
+		for color in palette {
+			data.extend_from_slice(&[color.b(), color.g(), color.r(), 0]);
+		}
+		data.extend_from_slice(pixels);
+		data
+	}
+
+	fn tga(image_type: u8, width: u16, height: u16, bpp: u8, descriptor: u8, pixels: &[u8]) -> Vec<u8> {
+		let mut data = vec![0, 0, image_type, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+		push_u16(&mut data, width);
+		push_u16(&mut data, height);
+		data.push(bpp);
+		data.push(descriptor);
+		data.extend_from_slice(pixels);
+		data
+	}
+
+	fn red() -> Color { Color::new(255, 0, 0) }
+	fn green() -> Color { Color::new(0, 255, 0) }
+	fn blue() -> Color { Color::new(0, 0, 255) }
+	fn white() -> Color { Color::new(255, 255, 255) }
+
+	fn expected(width: usize, height: usize, colors: Vec<Color>) -> Vec<LinearColor> {
+		texels(&Texture::new(width, height, colors))
+	}
+
+	#[test]
+	fn bmp_24_bit_bottom_up() {
+		//Bottom row first, each 6 byte row padded to 8
+		let pixels = [
+			0, 0, 255, 0, 255, 0, 0, 0,
+			255, 0, 0, 255, 255, 255, 0, 0,
+		];
+		let texture = decode_bmp(&bmp(2, 2, 24, &[], &pixels)).unwrap();
+
+		assert_eq!((texture.width(), texture.height()), (2, 2));
+		assert_eq!(texels(&texture), expected(2, 2, vec![blue(), white(), red(), green()]));
+	}
+
+	#[test]
+	fn bmp_8_bit_top_down_with_palette() {
+		let pixels = [2, 0, 1, 0];
+		let texture = decode_bmp(&bmp(3, -1, 8, &[red(), green(), blue()], &pixels)).unwrap();
+
+		assert_eq!(texels(&texture), expected(3, 1, vec![blue(), red(), green()]));
+	}
+
+	#[test]
+	fn bmp_rejects_malformed_files() {
+		let pixels = [0; 8];
+
+		let mut bad_magic = bmp(2, 1, 24, &[], &pixels);
+		bad_magic[0] = b'X';
+		assert!(decode_bmp(&bad_magic).is_err());
+
+		let truncated = bmp(2, 2, 24, &[], &pixels);
+		assert!(decode_bmp(&truncated).is_err());
+		assert!(decode_bmp(&truncated[..20]).is_err());
+
+		assert!(decode_bmp(&bmp(2, 1, 16, &[], &pixels)).is_err());
+		assert!(decode_bmp(&bmp(0, 1, 24, &[], &pixels)).is_err());
+		assert!(decode_bmp(&bmp(1, 1, 8, &[red()], &[1, 0, 0, 0])).is_err());
+
+		let mut compressed = bmp(2, 1, 24, &[], &pixels);
+		compressed[30] = 1;
+		assert!(decode_bmp(&compressed).is_err());
+
+		//Heights that can't be negated, and sizes that would overflow or take all the memory there is
+		assert!(decode_bmp(&bmp(1, i32::MIN, 24, &[], &[])).is_err());
+		assert!(decode_bmp(&bmp(1, i32::MAX, 24, &[], &[])).is_err());
+		assert!(decode_bmp(&bmp(i32::MAX, -i32::MAX, 32, &[], &[])).is_err());
+		assert!(decode_bmp(&bmp(16384, -16384, 32, &[], &pixels)).is_err());
+	}
+
+	#[test]
+	fn tga_uncompressed_bottom_up() {
+		let pixels = [
+			255, 0, 0, 255, 255, 255,
+			0, 0, 255, 0, 255, 0,
+		];
+		let texture = decode_tga(&tga(2, 2, 2, 24, 0, &pixels)).unwrap();
+
+		assert_eq!(texels(&texture), expected(2, 2, vec![red(), green(), blue(), white()]));
+	}
+
+	#[test]
+	fn tga_rle_top_down() {
+		//A run of three red then two raw pixels
+		let pixels = [
+			0x82, 0, 0, 255,
+			0x01, 0, 255, 0, 255, 0, 0,
+		];
+		let texture = decode_tga(&tga(10, 5, 1, 24, 0x20, &pixels)).unwrap();
+
+		assert_eq!(texels(&texture), expected(5, 1, vec![red(), red(), red(), green(), blue()]));
+	}
+
+	#[test]
+	fn tga_grayscale() {
+		let texture = decode_tga(&tga(3, 2, 1, 8, 0x20, &[0, 255])).unwrap();
+
+		assert_eq!(texels(&texture), expected(2, 1, vec![Color::new(0, 0, 0), white()]));
+	}
+
+	#[test]
+	fn tga_rejects_malformed_files() {
+		let pixels = [0; 12];
+
+		assert!(decode_tga(&pixels[..10]).is_err());
+		assert!(decode_tga(&tga(1, 2, 2, 24, 0, &pixels)).is_err());
+		assert!(decode_tga(&tga(2, 2, 2, 16, 0, &pixels)).is_err());
+		assert!(decode_tga(&tga(2, 0, 2, 24, 0, &pixels)).is_err());
+		assert!(decode_tga(&tga(2, 2, 2, 24, 0, &pixels[..11])).is_err());
+		assert!(decode_tga(&tga(10, 2, 2, 24, 0, &[0x81, 0, 0])).is_err());
+
+		let mut color_mapped = tga(2, 2, 2, 24, 0, &pixels);
+		color_mapped[1] = 1;
+		assert!(decode_tga(&color_mapped).is_err());
+
+		//Headers claiming far more pixels than the file holds are turned away before anything is allocated
+		assert!(decode_tga(&tga(2, 65535, 65535, 24, 0, &[0, 0, 0])).is_err());
+		assert!(decode_tga(&tga(2, 16384, 16384, 32, 0, &[0, 0, 0])).is_err());
+		assert!(decode_tga(&tga(10, 16384, 16384, 32, 0, &[0xff, 0, 0, 0, 0])).is_err());
+	}
+}
@@ -0,0 +1,301 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use framebuffer::Framebuffer;
+use raster::*;
+use vertex::Interpolate;
+use color::LinearColor;
+use color::BlendMode;
+use color::Rgba;
+
+//Width and height of a tile in pixels
+pub const TILE_SIZE: usize = 64;
+
+/*
+A triangle queued for drawing, id is handed back to the shader so it knows what it is shading
+*/
+enum Command<A> {
+	Opaque(ScreenTriangle<A>, usize),
+	Blended(ScreenTriangle<A>, usize, BlendMode),
+}
+
+/*
+A tile's own framebuffer and the commands that touch it, in the order they were queued
+*/
+struct Tile {
+	framebuffer: Framebuffer,
+	commands: Vec<usize>,
+
+	//Pixels written by the last render()
+	written: usize,
+}
+
+/*
+Sorts screen space triangles into tiles then fills the tiles in parallel. Each tile runs its
+commands in the order they were queued so blending works as usual, and every pixel goes through
+exactly the same steps whatever the thread count, so the output doesn't depend on it
+*/
+pub struct TileRenderer<A> {
+	width: usize,
+	height: usize,
+	columns: usize,
+
+	tiles: Vec<Mutex<Tile>>,
+	commands: Vec<Command<A>>,
+	threads: usize,
+}
+
+impl<A: Interpolate + Send + Sync> TileRenderer<A> {
+	pub fn new(width: usize, height: usize, threads: usize) -> TileRenderer<A> {
+		let columns = width.div_ceil(TILE_SIZE);
+		let rows = height.div_ceil(TILE_SIZE);
+
+		let mut tiles = Vec::with_capacity(columns * rows);
+		for row in 0..rows {
+			for column in 0..columns {
+				let (left, top) = (column * TILE_SIZE, row * TILE_SIZE);
+
+				tiles.push(Mutex::new(Tile {
+					framebuffer: Framebuffer::tile(left, top, TILE_SIZE.min(width - left), TILE_SIZE.min(height - top)),
+					commands: Vec::new(),
+					written: 0,
+				}));
+			}
+		}
+
+		TileRenderer {
+			width: width,
+			height: height,
+			columns: columns,
+			tiles: tiles,
+			commands: Vec::new(),
+			threads: threads.max(1),
+		}
+	}
+
+	//As many threads as the machine has cores
+	pub fn available_threads() -> usize {
+		thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+	}
+
+	pub fn set_threads(&mut self, threads: usize) {
+		self.threads = threads.max(1);
+	}
+
+	pub fn threads(&self) -> usize {
+		self.threads
+	}
+
+	//Samples per pixel in the tiles, render() needs a framebuffer with the same number
+	pub fn set_samples(&mut self, samples: usize) {
+		for tile in self.tiles.iter_mut() {
+			tile.get_mut().unwrap().framebuffer.set_samples(samples);
+		}
+	}
+
+	//Queues a triangle to be shaded by the opaque shader passed to render()
+	pub fn draw(&mut self, tri: &ScreenTriangle<A>, id: usize) {
+		let command = self.commands.len();
+		self.commands.push(Command::Opaque(*tri, id));
+		self.bin(tri, command);
+	}
+
+	//Queues a triangle to be shaded by the blended shader passed to render(), see draw_triangle_blended()
+	pub fn draw_blended(&mut self, tri: &ScreenTriangle<A>, id: usize, mode: BlendMode) {
+		let command = self.commands.len();
+		self.commands.push(Command::Blended(*tri, id, mode));
+		self.bin(tri, command);
+	}
+
+	//Adds the command to every tile the triangle's bounding box touches
+	fn bin(&mut self, tri: &ScreenTriangle<A>, command: usize) {
+		let (p0, p1, p2) = (tri[0].position(), tri[1].position(), tri[2].position());
+
+		let min_x = p0.x().min(p1.x()).min(p2.x()).floor().max(0.0);
+		let max_x = p0.x().max(p1.x()).max(p2.x()).ceil().min(self.width as f32 - 1.0);
+		let min_y = p0.y().min(p1.y()).min(p2.y()).floor().max(0.0);
+		let max_y = p0.y().max(p1.y()).max(p2.y()).ceil().min(self.height as f32 - 1.0);
+
+		if !(min_x <= max_x && min_y <= max_y) {
+			return;
+		}
+
+		for row in (min_y as usize / TILE_SIZE)..(max_y as usize / TILE_SIZE + 1) {
+			for column in (min_x as usize / TILE_SIZE)..(max_x as usize / TILE_SIZE + 1) {
+				self.tiles[column + row * self.columns].get_mut().unwrap().commands.push(command);
+			}
+		}
+	}
+
+	//Clears every tile to background, runs the queued commands and copies the tiles into framebuffer.
+	//The queue is emptied for the next frame. Returns how many pixels were written
+	pub fn render<S, B>(&mut self, framebuffer: &mut Framebuffer, background: LinearColor, shade: S, shade_blended: B) -> usize
+		where S: Fn(usize, &Fragment<A>) -> LinearColor + Sync,
+			  B: Fn(usize, &Fragment<A>) -> Rgba + Sync
+	{
+		let next = AtomicUsize::new(0);
+		let (tiles, commands) = (&self.tiles, &self.commands);
+		let (shade, shade_blended) = (&shade, &shade_blended);
+
+		//Tiles are handed out one at a time so threads that get easy ones go back for more
+		let work = || {
+			loop {
+				let index = next.fetch_add(1, Ordering::Relaxed);
+				if index >= tiles.len() {
+					break;
+				}
+
+				let mut tile = tiles[index].lock().unwrap();
+				let tile = &mut *tile;
+				tile.framebuffer.clear(background);
+				tile.written = 0;
+
+				for &command in &tile.commands {
+					tile.written += match commands[command] {
+						Command::Opaque(ref tri, id) => {
+							draw_triangle(&mut tile.framebuffer, tri, |fragment| shade(id, fragment))
+						}
+						Command::Blended(ref tri, id, mode) => {
+							draw_triangle_blended(&mut tile.framebuffer, tri, mode, |fragment| shade_blended(id, fragment))
+						}
+					};
+				}
+			}
+		};
+
+		if self.threads == 1 {
+			work();
+		} else {
+			thread::scope(|scope| {
+				for _ in 0..self.threads {
+					scope.spawn(work);
+				}
+			});
+		}
+
+		let mut written = 0;
+		for tile in self.tiles.iter_mut() {
+			let tile = tile.get_mut().unwrap();
+			framebuffer.blit(&tile.framebuffer);
+			tile.commands.clear();
+			written += tile.written;
+		}
+		self.commands.clear();
+
+		written
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use pointf::Point3f;
+
+	const WIDTH: usize = 200;
+	const HEIGHT: usize = 150;
+
+	fn triangle(a: (f32, f32, f32), b: (f32, f32, f32), c: (f32, f32, f32)) -> ScreenTriangle<f32> {
+		[ScreenVertex::new(Point3f::new(a.0, a.1, -a.2), 0.0),
+		 ScreenVertex::new(Point3f::new(b.0, b.1, -b.2), 0.5),
+		 ScreenVertex::new(Point3f::new(c.0, c.1, -c.2), 1.0)]
+	}
+
+	//Opaque triangles at different depths crossing each other and the tile edges, then translucent
+	//ones over them. The frame isn't a whole number of tiles either way
+	fn scene() -> (Vec<ScreenTriangle<f32>>, Vec<(ScreenTriangle<f32>, BlendMode)>) {
+		let opaque = vec![
+			//Straddles the corner where four tiles meet, and the edge between two
+			triangle((50.0, 50.0, 2.0), (80.0, 55.0, 2.0), (60.0, 80.0, 2.0)),
+			triangle((60.5, 10.0, 3.0), (70.5, 10.0, 3.0), (65.0, 120.0, 3.0)),
+			//Covers most of the frame behind the others, off every edge
+			triangle((-40.0, -30.0, 8.0), (260.0, 20.0, 4.0), (30.0, 190.0, 6.0)),
+			triangle((190.0, 140.0, 1.5), (120.0, 149.5, 5.0), (199.5, 70.0, 1.5)),
+		];
+		let blended = vec![
+			(triangle((10.0, 100.0, 1.0), (150.0, 60.0, 1.0), (130.0, 145.0, 1.0)), BlendMode::Alpha),
+			(triangle((100.0, 0.0, 2.5), (199.0, 0.0, 2.5), (128.0, 100.0, 2.5)), BlendMode::Additive),
+		];
+		(opaque, blended)
+	}
+
+	//Colors only depend on the triangle and the interpolated attribute
+	fn shade(id: usize, fragment: &Fragment<f32>) -> LinearColor {
+		LinearColor::new(id as f32 * 0.2, fragment.attributes(), 0.25)
+	}
+
+	fn shade_blended(id: usize, fragment: &Fragment<f32>) -> Rgba {
+		Rgba::premultiplied(LinearColor::new(1.0, fragment.attributes(), id as f32 * 0.1), 0.5)
+	}
+
+	fn render(threads: usize, samples: usize) -> (Framebuffer, usize) {
+		let (opaque, blended) = scene();
+		let mut renderer = TileRenderer::new(WIDTH, HEIGHT, threads);
+		renderer.set_samples(samples);
+		for (i, tri) in opaque.iter().enumerate() {
+			renderer.draw(tri, i);
+		}
+		for (i, &(ref tri, mode)) in blended.iter().enumerate() {
+			renderer.draw_blended(tri, i, mode);
+		}
+
+		let mut framebuffer = Framebuffer::new(WIDTH, HEIGHT);
+		framebuffer.set_samples(samples);
+		let written = renderer.render(&mut framebuffer, LinearColor::new(0.0, 0.0, 1.0), shade, shade_blended);
+		(framebuffer, written)
+	}
+
+	//Depth can be off by tolerance, but not whether anything was drawn
+	fn assert_same(a: &Framebuffer, b: &Framebuffer, tolerance: f32) {
+		for y in 0..HEIGHT {
+			for x in 0..WIDTH {
+				assert_eq!(a.pixel(x, y), b.pixel(x, y), "pixel {}, {}", x, y);
+				assert_eq!(a.depth(x, y) > 0.0, b.depth(x, y) > 0.0, "coverage {}, {}", x, y);
+				assert!((a.depth(x, y) - b.depth(x, y)).abs() <= tolerance, "depth {}, {}", x, y);
+			}
+		}
+	}
+
+	#[test]
+	fn thread_count_does_not_change_the_frame() {
+		for &samples in [1, 4].iter() {
+			let (single, single_written) = render(1, samples);
+			for &threads in [2, 3, 8].iter() {
+				let (multi, multi_written) = render(threads, samples);
+				assert_eq!(single_written, multi_written);
+				assert_same(&single, &multi, 0.0);
+			}
+		}
+	}
+
+	#[test]
+	fn tile_edges_match_drawing_the_whole_frame() {
+		//Flat colors, so only which pixels each triangle covers can make a difference
+		let (opaque, blended) = scene();
+		let mut whole = Framebuffer::new(WIDTH, HEIGHT);
+		whole.clear(LinearColor::new(0.0, 0.0, 1.0));
+		let mut written = 0;
+		for (i, tri) in opaque.iter().enumerate() {
+			written += draw_triangle(&mut whole, tri, |_| LinearColor::new(i as f32 * 0.2, 0.5, 0.25));
+		}
+		for (i, &(ref tri, mode)) in blended.iter().enumerate() {
+			written += draw_triangle_blended(&mut whole, tri, mode, |_| Rgba::new(0.5, 0.25, i as f32 * 0.1, 0.5));
+		}
+
+		let mut renderer = TileRenderer::new(WIDTH, HEIGHT, 4);
+		for (i, tri) in opaque.iter().enumerate() {
+			renderer.draw(tri, i);
+		}
+		for (i, &(ref tri, mode)) in blended.iter().enumerate() {
+			renderer.draw_blended(tri, i, mode);
+		}
+		let mut tiled = Framebuffer::new(WIDTH, HEIGHT);
+		let tiled_written = renderer.render(&mut tiled, LinearColor::new(0.0, 0.0, 1.0),
+											|i, _| LinearColor::new(i as f32 * 0.2, 0.5, 0.25),
+											|i, _| Rgba::new(0.5, 0.25, i as f32 * 0.1, 0.5));
+
+		assert_eq!(written, tiled_written);
+		//Spans start at the tile's edge instead of the triangle's, which can move depth by a rounding error
+		assert_same(&whole, &tiled, 1e-6);
+	}
+}
@@ -5,6 +5,10 @@ use sdl2::render;
 use pointf::Point2f;
 use pointf::Point3f;
 use color::Color;
+use color::LinearColor;
+use vertex::Attributes;
+use lighting::Material;
+use color::BlendMode;
 
 #[derive(Copy, Clone)]
 pub struct Triangle3D {
@@ -13,6 +17,13 @@ pub struct Triangle3D {
 	v2: Point3f,
 
 	color: Color,
+	attributes: [Attributes; 3],
+	texture: Option<usize>,
+	material: Material,
+	casts_shadow: bool,
+
+	//None for opaque triangles
+	blend: Option<BlendMode>,
 }
 
 impl Triangle3D {
@@ -22,91 +33,60 @@ impl Triangle3D {
 			v1: v1,
 			v2: v2,
 			color: Color::new(255,255,255),
+			attributes: [Attributes::new(Color::new(255,255,255)); 3],
+			texture: None,
+			material: Material::standard(),
+			casts_shadow: true,
+			blend: None,
 		}
 	}
 	
+	//Sets the triangle color along with the color of every vertex, color is sRGB encoded
 	pub fn set_color(&mut self, color: Color) {
 		self.color = color;
-	}
-
-	pub fn to_screen_space(&self, cam_pos: Point3f, cam_rot: Point3f) -> (Option<Triangle2D>, Option<Triangle2D>) {
-		let mut v0 = self.v0.to_camera_view(cam_pos, cam_rot);//.perspective_project();
-		let mut v1 = self.v1.to_camera_view(cam_pos, cam_rot);//.perspective_project();
-		let mut v2 = self.v2.to_camera_view(cam_pos, cam_rot);//.perspective_project();
-
-		//Sort vertices from z- to z+ (v0 < v1 < v2)
-		if v1.z() < v0.z() {
-			swap(&mut v1, &mut v0);
-		}
-		if v2.z() < v1.z() {
-			swap(&mut v2, &mut v1);
+		for attributes in self.attributes.iter_mut() {
+			attributes.set_color(LinearColor::from_srgb(color));
 		}
+	}
 
-		if v2.z() > 0.0 {
-
-			//If all points are behind camera, don't even try
-			if v0.z() > 0.0 {
-				return (None, None);
-			}
-
-			//First case, only v2 is behind camera (e.g. v1.x < 0)
-			if v1.z() <= 0.0 {
-				let mut plane_1 = Point3f::intersect_plane(v0,v2, Point3f::new(0.0,0.0,-1.0));
-				let mut plane_2 = Point3f::intersect_plane(v1,v2, Point3f::new(0.0,0.0,-1.0));
-
-				if plane_1 == None || plane_2 == None {
-					return (None, None);
-				}
-
-				let mut plane_1 = plane_1.unwrap();
-				let mut plane_2 = plane_2.unwrap();
-
-				//Sort so plane_1 is the highest point
-				if plane_1.y() < plane_2.y() { swap(&mut plane_1, &mut plane_2); }
-
-				//Sort v0 & v1, so v1 is higher
-				if v1.y() < v0.y() {
-					swap(&mut v0, &mut v1);
-				}
+	pub fn set_attributes(&mut self, vert: usize, attributes: Attributes) {
+		self.attributes[vert] = attributes;
+	}
 
-				v0 = v0.perspective_project();
-				v1 = v1.perspective_project();
-				plane_1 = plane_1.perspective_project();
-				plane_2 = plane_2.perspective_project();
+	//Index of the texture in the map, None for flat colored triangles
+	pub fn set_texture(&mut self, texture: Option<usize>) {
+		self.texture = texture;
+	}
 
-				//Create first triangle with (v0,v1,plane_1)
-				let tri1 = Triangle2D::new(v0.xy(), v1.xy(), plane_1.xy());
+	pub fn set_material(&mut self, material: Material) {
+		self.material = material;
+	}
 
-				//Create second triangle with (v0, plane_1, plane_2)
-				let tri2 = Triangle2D::new(v0.xy(), plane_1.xy(), plane_2.xy());
+	pub fn set_casts_shadow(&mut self, casts_shadow: bool) {
+		self.casts_shadow = casts_shadow;
+	}
 
-				return (Some(tri1), Some(tri2));
-			} 
-			//Second case, both v2 & v1 are behind the camera
-			else if v1.z() > 0.0 {
-				let mut plane_1 = Point3f::intersect_plane(v0,v1, Point3f::new(0.0,0.0,-1.0));
-				let mut plane_2 = Point3f::intersect_plane(v0,v2, Point3f::new(0.0,0.0,-1.0));
+	pub fn set_blend(&mut self, blend: Option<BlendMode>) {
+		self.blend = blend;
+	}
 
-				if plane_1 == None || plane_2 == None {
-					return (None, None);
-				}
+	pub fn to_screen_space(&self, cam_pos: Point3f, cam_rot: Point3f) -> (Option<Triangle2D>, Option<Triangle2D>) {
+		let v0 = self.v0.to_camera_view(cam_pos, cam_rot);
+		let v1 = self.v1.to_camera_view(cam_pos, cam_rot);
+		let v2 = self.v2.to_camera_view(cam_pos, cam_rot);
 
-				v1 = plane_1.unwrap();
-				v2 = plane_2.unwrap();
-				
-				v0 = v0.perspective_project();
-				v1 = v1.perspective_project();
-				v2 = v2.perspective_project();
+		clip_to_screen(v0, v1, v2)
+	}
 
-				return (Some(Triangle2D::new(v0.xy(), v1.xy(), v2.xy())), None);
-			}
-		}
-				
-		v0 = v0.perspective_project();
-		v1 = v1.perspective_project();
-		v2 = v2.perspective_project();
+	//Unit normal following the right hand rule around v0, v1, v2
+	pub fn normal(&self) -> Option<Point3f> {
+		(self.v1 - self.v0).cross(self.v2 - self.v0).normalize()
+	}
 
-		(Some(Triangle2D::new(v0.xy(), v1.xy(), v2.xy())), None)
+	//Swaps v1 and v2 along with their attributes, turning the triangle to face the other way
+	pub fn flip_winding(&mut self) {
+		swap(&mut self.v1, &mut self.v2);
+		self.attributes.swap(1, 2);
 	}
 
 	pub fn v0(&self) -> Point3f {
@@ -125,6 +105,25 @@ impl Triangle3D {
 		self.color
 	}
 
+	pub fn attributes(&self, vert: usize) -> Attributes {
+		self.attributes[vert]
+	}
+
+	pub fn texture(&self) -> Option<usize> {
+		self.texture
+	}
+
+	pub fn material(&self) -> Material {
+		self.material
+	}
+
+	pub fn casts_shadow(&self) -> bool {
+		self.casts_shadow
+	}
+
+	pub fn blend(&self) -> Option<BlendMode> {
+		self.blend
+	}
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -229,6 +228,83 @@ impl Triangle2D {
 	}
 }
 
+//Clips a triangle that is already in camera view against the near plane and projects it
+pub fn clip_to_screen(mut v0: Point3f, mut v1: Point3f, mut v2: Point3f) -> (Option<Triangle2D>, Option<Triangle2D>) {
+	//Sort vertices from z- to z+ (v0 < v1 < v2)
+	if v1.z() < v0.z() {
+		swap(&mut v1, &mut v0);
+	}
+	if v2.z() < v1.z() {
+		swap(&mut v2, &mut v1);
+	}
+
+	if v2.z() > 0.0 {
+
+		//If all points are behind camera, don't even try
+		if v0.z() > 0.0 {
+			return (None, None);
+		}
+
+		//First case, only v2 is behind camera (e.g. v1.x < 0)
+		if v1.z() <= 0.0 {
+			let mut plane_1 = Point3f::intersect_plane(v0,v2, Point3f::new(0.0,0.0,-1.0));
+			let mut plane_2 = Point3f::intersect_plane(v1,v2, Point3f::new(0.0,0.0,-1.0));
+
+			if plane_1 == None || plane_2 == None {
+				return (None, None);
+			}
+
+			let mut plane_1 = plane_1.unwrap();
+			let mut plane_2 = plane_2.unwrap();
+
+			//Sort so plane_1 is the highest point
+			if plane_1.y() < plane_2.y() { swap(&mut plane_1, &mut plane_2); }
+
+			//Sort v0 & v1, so v1 is higher
+			if v1.y() < v0.y() {
+				swap(&mut v0, &mut v1);
+			}
+
+			v0 = v0.perspective_project();
+			v1 = v1.perspective_project();
+			plane_1 = plane_1.perspective_project();
+			plane_2 = plane_2.perspective_project();
+
+			//Create first triangle with (v0,v1,plane_1)
+			let tri1 = Triangle2D::new(v0.xy(), v1.xy(), plane_1.xy());
+
+			//Create second triangle with (v0, plane_1, plane_2)
+			let tri2 = Triangle2D::new(v0.xy(), plane_1.xy(), plane_2.xy());
+
+			return (Some(tri1), Some(tri2));
+		} 
+		//Second case, both v2 & v1 are behind the camera
+		else if v1.z() > 0.0 {
+			let mut plane_1 = Point3f::intersect_plane(v0,v1, Point3f::new(0.0,0.0,-1.0));
+			let mut plane_2 = Point3f::intersect_plane(v0,v2, Point3f::new(0.0,0.0,-1.0));
+
+			if plane_1 == None || plane_2 == None {
+				return (None, None);
+			}
+
+			v1 = plane_1.unwrap();
+			v2 = plane_2.unwrap();
+			
+			v0 = v0.perspective_project();
+			v1 = v1.perspective_project();
+			v2 = v2.perspective_project();
+
+			return (Some(Triangle2D::new(v0.xy(), v1.xy(), v2.xy())), None);
+		}
+	}
+			
+	v0 = v0.perspective_project();
+	v1 = v1.perspective_project();
+	v2 = v2.perspective_project();
+
+	(Some(Triangle2D::new(v0.xy(), v1.xy(), v2.xy())), None)
+}
+
 //Vector Cross Product
 fn vxs(x1: f32, y1: f32, x2: f32, y2: f32) -> f32 {
 	(x1*y2) - (x2*y1)
@@ -0,0 +1,180 @@
+use pointf::Point2f;
+use pointf::Point3f;
+use color::Color;
+use color::LinearColor;
+
+/*
+Anything that can be interpolated across a triangle
+*/
+pub trait Interpolate: Copy {
+	fn sum(&self, rhs: &Self) -> Self;
+	fn weighted(&self, weight: f32) -> Self;
+
+	fn lerp(&self, rhs: &Self, t: f32) -> Self {
+		self.weighted(1.0 - t).sum(&rhs.weighted(t))
+	}
+}
+
+impl Interpolate for f32 {
+	fn sum(&self, rhs: &f32) -> f32 {
+		self + rhs
+	}
+
+	fn weighted(&self, weight: f32) -> f32 {
+		self * weight
+	}
+}
+
+impl Interpolate for Point2f {
+	fn sum(&self, rhs: &Point2f) -> Point2f {
+		*self + *rhs
+	}
+
+	fn weighted(&self, weight: f32) -> Point2f {
+		*self * weight
+	}
+}
+
+impl Interpolate for Point3f {
+	fn sum(&self, rhs: &Point3f) -> Point3f {
+		*self + *rhs
+	}
+
+	fn weighted(&self, weight: f32) -> Point3f {
+		*self * weight
+	}
+}
+
+/*
+The per-vertex attributes carried by map geometry
+*/
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Attributes {
+	//Color in linear light, so interpolating it blends light rather than sRGB values
+	r: f32,
+	g: f32,
+	b: f32,
+
+	//Coverage between 0 and 1
+	a: f32,
+
+	//Light from the shadow casting light, kept out of the color so the shadow can be looked up per pixel
+	shadowed: LinearColor,
+
+	uv: Point2f,
+	normal: Point3f,
+
+	//From the surface towards the camera in world space, filled in when the vertex is projected
+	view: Point3f,
+
+	//Distance in front of the camera, filled in when the vertex is projected
+	depth: f32,
+}
+
+impl Attributes {
+	//color is sRGB encoded, as it comes from map files
+	pub fn new(color: Color) -> Attributes {
+		let color = LinearColor::from_srgb(color);
+
+		Attributes {
+			r: color.r(),
+			g: color.g(),
+			b: color.b(),
+			a: 1.0,
+			shadowed: LinearColor::black(),
+			uv: Point2f::new(0.0, 0.0),
+			normal: Point3f::new(0.0, 0.0, 0.0),
+			view: Point3f::new(0.0, 0.0, 0.0),
+			depth: 0.0,
+		}
+	}
+
+	pub fn color(&self) -> LinearColor {
+		LinearColor::new(self.r, self.g, self.b)
+	}
+
+	pub fn set_color(&mut self, color: LinearColor) {
+		self.r = color.r();
+		self.g = color.g();
+		self.b = color.b();
+	}
+
+	pub fn alpha(&self) -> f32 {
+		self.a.max(0.0).min(1.0)
+	}
+
+	//alpha is 0 to 255 like map files give it
+	pub fn set_alpha(&mut self, alpha: u8) {
+		self.a = alpha as f32 / 255.0;
+	}
+
+	pub fn shadowed(&self) -> LinearColor {
+		self.shadowed
+	}
+
+	pub fn set_shadowed(&mut self, shadowed: LinearColor) {
+		self.shadowed = shadowed;
+	}
+
+	pub fn uv(&self) -> Point2f {
+		self.uv
+	}
+
+	pub fn set_uv(&mut self, uv: Point2f) {
+		self.uv = uv;
+	}
+
+	pub fn normal(&self) -> Point3f {
+		self.normal
+	}
+
+	pub fn set_normal(&mut self, normal: Point3f) {
+		self.normal = normal;
+	}
+
+	pub fn view(&self) -> Point3f {
+		self.view
+	}
+
+	pub fn set_view(&mut self, view: Point3f) {
+		self.view = view;
+	}
+
+	pub fn depth(&self) -> f32 {
+		self.depth
+	}
+
+	pub fn set_depth(&mut self, depth: f32) {
+		self.depth = depth;
+	}
+}
+
+impl Interpolate for Attributes {
+	fn sum(&self, rhs: &Attributes) -> Attributes {
+		Attributes {
+			r: self.r + rhs.r,
+			g: self.g + rhs.g,
+			b: self.b + rhs.b,
+			a: self.a + rhs.a,
+			shadowed: self.shadowed + rhs.shadowed,
+			uv: self.uv + rhs.uv,
+			normal: self.normal + rhs.normal,
+			view: self.view + rhs.view,
+			depth: self.depth + rhs.depth,
+		}
+	}
+
+	fn weighted(&self, weight: f32) -> Attributes {
+		Attributes {
+			r: self.r * weight,
+			g: self.g * weight,
+			b: self.b * weight,
+			a: self.a * weight,
+			shadowed: self.shadowed * weight,
+			uv: self.uv * weight,
+			normal: self.normal * weight,
+			view: self.view * weight,
+			depth: self.depth * weight,
+		}
+	}
+}
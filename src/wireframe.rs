@@ -0,0 +1,141 @@
+use std::mem::swap;
+
+use color::Color;
+use color::LinearColor;
+use framebuffer::Framebuffer;
+use raster::{ScreenVertex, ScreenTriangle};
+use vertex::Interpolate;
+
+//How much further away than the stored depth a line may be and still pass the depth test, as a
+//share of 1/w. Edges sit exactly on their own triangle so they need a little slack to show
+const DEPTH_BIAS: f32 = 0.01;
+
+/*
+How triangle edges are drawn over the frame
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum WireframeMode {
+	Off,
+
+	//Edges over the shaded scene, hidden where something is in front of them
+	Overlay,
+
+	//Only the edges, with the triangles still hiding the ones behind them
+	HiddenLine,
+
+	//Only the edges, all of them, including ones behind something
+	All,
+}
+
+impl WireframeMode {
+	pub fn next(&self) -> WireframeMode {
+		match *self {
+			WireframeMode::Off => WireframeMode::Overlay,
+			WireframeMode::Overlay => WireframeMode::HiddenLine,
+			WireframeMode::HiddenLine => WireframeMode::All,
+			WireframeMode::All => WireframeMode::Off,
+		}
+	}
+
+	//Whether the shaded scene is replaced by the edges alone
+	pub fn lines_only(&self) -> bool {
+		*self == WireframeMode::HiddenLine || *self == WireframeMode::All
+	}
+
+	pub fn depth_test(&self) -> bool {
+		*self != WireframeMode::All
+	}
+}
+
+//Draws the three edges of a triangle, see draw_line()
+pub fn draw_wireframe<A: Interpolate>(fb: &mut Framebuffer, tri: &ScreenTriangle<A>, color: LinearColor, depth_test: bool) {
+	for i in 0..3 {
+		draw_line(fb, &tri[i], &tri[(i + 1) % 3], color, depth_test);
+	}
+}
+
+//Draws an anti-aliased line between two projected vertices with Xiaolin Wu's algorithm. It goes
+//straight into the resolved pixels like text does, blending by coverage in linear light. With
+//depth_test set it is hidden behind whatever is in the depth buffer, and it never writes depth
+pub fn draw_line<A: Interpolate>(fb: &mut Framebuffer, a: &ScreenVertex<A>, b: &ScreenVertex<A>, color: LinearColor, depth_test: bool) {
+	let (mut x0, mut y0, mut d0) = (a.position().x(), a.position().y(), 1.0 / a.w());
+	let (mut x1, mut y1, mut d1) = (b.position().x(), b.position().y(), 1.0 / b.w());
+	if ![x0, y0, d0, x1, y1, d1].iter().all(|v| v.is_finite()) {
+		return;
+	}
+
+	//Walk along whichever axis the line covers more of, from left to right
+	let steep = (y1 - y0).abs() > (x1 - x0).abs();
+	if steep {
+		swap(&mut x0, &mut y0);
+		swap(&mut x1, &mut y1);
+	}
+	if x0 > x1 {
+		swap(&mut x0, &mut x1);
+		swap(&mut y0, &mut y1);
+		swap(&mut d0, &mut d1);
+	}
+
+	let (dx, dy) = (x1 - x0, y1 - y0);
+	let gradient = if dx == 0.0 { 1.0 } else { dy / dx };
+
+	//Columns or rows on screen, whichever the line is walked along
+	let limit = if steep { fb.height() } else { fb.width() } as f32;
+
+	//1/w is linear in screen space, so depth along the line is too
+	let depth_at = |x: f32| if dx == 0.0 { d0 } else { d0 + (d1 - d0) * ((x - x0) / dx).max(0.0).min(1.0) };
+	let mut plot = |major: i64, minor: i64, coverage: f32| {
+		let (x, y) = if steep { (minor, major) } else { (major, minor) };
+		plot_pixel(fb, x, y, color, coverage, depth_at(major as f32), depth_test);
+	};
+
+	//The ends only cover part of their pixel along the line
+	let x_start = x0.round();
+	let y_start = y0 + gradient * (x_start - x0);
+	let gap = 1.0 - fpart(x0 + 0.5);
+	plot(x_start as i64, y_start.floor() as i64, (1.0 - fpart(y_start)) * gap);
+	plot(x_start as i64, y_start.floor() as i64 + 1, fpart(y_start) * gap);
+
+	let x_end = x1.round();
+	let y_end = y1 + gradient * (x_end - x1);
+	let gap = fpart(x1 + 0.5);
+	plot(x_end as i64, y_end.floor() as i64, (1.0 - fpart(y_end)) * gap);
+	plot(x_end as i64, y_end.floor() as i64 + 1, fpart(y_end) * gap);
+
+	//Only the part of the line between the ends that is on screen gets walked
+	let first = (x_start + 1.0).max(0.0);
+	let last = (x_end - 1.0).min(limit - 1.0);
+
+	let mut major = first;
+	while major <= last {
+		let minor = y_start + gradient * (major - x_start);
+		plot(major as i64, minor.floor() as i64, 1.0 - fpart(minor));
+		plot(major as i64, minor.floor() as i64 + 1, fpart(minor));
+		major += 1.0;
+	}
+}
+
+//Fractional part that stays positive for negative numbers
+fn fpart(v: f32) -> f32 {
+	v - v.floor()
+}
+
+fn plot_pixel(fb: &mut Framebuffer, x: i64, y: i64, color: LinearColor, coverage: f32, inv_w: f32, depth_test: bool) {
+	if x < 0 || y < 0 || x >= fb.width() as i64 || y >= fb.height() as i64 || coverage <= 0.0 {
+		return;
+	}
+
+	let (x, y) = (x as usize, y as usize);
+	if depth_test && inv_w * (1.0 + DEPTH_BIAS) < fb.depth(x, y) {
+		return;
+	}
+
+	let offset = x * 3 + y * fb.pitch();
+	let pixels = fb.pixels_mut();
+	let under = LinearColor::from_srgb(Color::new(pixels[offset], pixels[offset + 1], pixels[offset + 2]));
+	let blended = under.lerp(color, coverage).to_srgb();
+
+	pixels[offset] = blended.r();
+	pixels[offset + 1] = blended.g();
+	pixels[offset + 2] = blended.b();
+}